@@ -1,27 +1,130 @@
 extern crate rustbox;
 extern crate rustify;
 
-#[derive(Debug, PartialEq)]
+use plays;
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Action {
   Select,
   PlayNextTrack,
+  PlayPreviousTrack,
   QueueTrack,
+  QueueNext,
+  ReplaceQueue,
+  QueueAll,
+
+  /// Queues the track with the given Spotify URI.
+  QueueUri(String),
   TogglePlayback,
 
+  /// Pauses the playback, unless it's already paused. Unlike with
+  /// `TogglePlayback` other programs don't need to know whether we're
+  /// playing, which might have changed by the time the action is carried out.
+  Pause,
+
+  /// Continues the paused track, or starts the next one in the queue if
+  /// nothing is playing.
+  Resume,
+
+  /// Seeks the given number of milliseconds (backwards if negative) in the
+  /// playing track.
+  Seek(i64),
+
+  GoToAlbum,
+  GoToArtist,
+
+  AddToPlaylist,
+  RemoveTrack,
+  MoveTrackDown,
+  MoveTrackUp,
+
+  /// Wraps an action that the user has confirmed (e.g. through
+  /// `CommandParser::confirm`) and should be carried out.
+  Confirmed(Box<Action>),
+
   FilterList(String),
   SearchTrack(String),
 
+  /// Searches for tracks without showing them, for other programs (see
+  /// `control::Status::search_results`), so that the interface isn't taken
+  /// over by a search started elsewhere.
+  FindTracks(String),
+
+  CreatePlaylist(String),
+  CreateFolder(String),
+  RenamePlaylist(String),
+  DeletePlaylist,
+
+  /// Reads the configuration file again and applies it.
+  ReloadConfig,
+
+  /// Shows the most played tracks and artists during the period.
+  ShowStats(plays::Period),
+
+  /// Sorts the list of tracks by the key, or shows it in its own order again.
+  SortList(Option<plays::SortKey>),
+
+  /// Only shows the tracks in the list that have never been played.
+  ShowUnplayed,
+
+  /// Contains the name of the new playlist and whether or not the current
+  /// track should be saved before the queue.
+  SaveQueue(String, bool),
+
+  /// A command that couldn't be parsed, containing a message for the user.
+  InvalidCommand(String),
+
   MoveBottom,
   MoveDown,
   MoveTop,
   MoveUp,
 
+  ToggleVisual,
+  ToggleMark,
+  MarkAll,
+  InvertMarks,
+
   Back,
   Quit,
 
   Noop,
 }
 
+/// Returns the action with the given name (e.g. `queue_track`), used for
+/// binding keys in the configuration. Only actions without an argument can be
+/// bound.
+pub fn from_name(name: &str) -> Option<Action> {
+  return match name {
+    "select" => Some(Action::Select),
+    "play_next_track" => Some(Action::PlayNextTrack),
+    "play_previous_track" => Some(Action::PlayPreviousTrack),
+    "queue_track" => Some(Action::QueueTrack),
+    "queue_next" => Some(Action::QueueNext),
+    "replace_queue" => Some(Action::ReplaceQueue),
+    "queue_all" => Some(Action::QueueAll),
+    "toggle_playback" => Some(Action::TogglePlayback),
+    "go_to_album" => Some(Action::GoToAlbum),
+    "go_to_artist" => Some(Action::GoToArtist),
+    "add_to_playlist" => Some(Action::AddToPlaylist),
+    "remove_track" => Some(Action::RemoveTrack),
+    "move_track_down" => Some(Action::MoveTrackDown),
+    "move_track_up" => Some(Action::MoveTrackUp),
+    "delete_playlist" => Some(Action::DeletePlaylist),
+    "reload_config" => Some(Action::ReloadConfig),
+    "move_bottom" => Some(Action::MoveBottom),
+    "move_down" => Some(Action::MoveDown),
+    "move_top" => Some(Action::MoveTop),
+    "move_up" => Some(Action::MoveUp),
+    "toggle_visual" => Some(Action::ToggleVisual),
+    "toggle_mark" => Some(Action::ToggleMark),
+    "mark_all" => Some(Action::MarkAll),
+    "invert_marks" => Some(Action::InvertMarks),
+    "back" => Some(Action::Back),
+    "quit" => Some(Action::Quit),
+    _ => None,
+  }
+}
+
 pub fn next_action(rustify_event: Option<rustify::Event>) -> Action {
   if rustify_event.is_some() {
     return next_rustify_event(rustify_event.unwrap());
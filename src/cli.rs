@@ -0,0 +1,223 @@
+use playlist_tree;
+use rustify;
+use std;
+use std::path::{PathBuf};
+
+pub const USAGE: &'static str = "\
+Usage: neubauten [options] [command]
+
+Without a command the interface is started.
+
+Commands:
+  play <uri>              Play a track and exit when it has ended
+  search <query>          Print the tracks matching the query
+  playlists               Print the playlists and folders
+  ctl <command>           Control the running instance, the commands are
+                          status, toggle, next, previous, seek <seconds>
+                          (e.g. +30 or -10), queue <uri> and search <query>
+
+Options:
+  --config <path>         Read the configuration from this file
+  --username <username>   Sign in as this user
+  --no-tui                Sign in on the terminal and keep running without
+                          the interface
+  --logout                Forget the remembered credentials
+  --print-default-config  Print a configuration file with the defaults
+  -h, --help              Print this message
+  -V, --version           Print the version
+";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+  /// Start the interface (or keep running without it, see `no_tui`).
+  Interface,
+
+  /// Contains the Spotify URI of the track.
+  Play(String),
+
+  /// Contains the search query.
+  Search(String),
+
+  Playlists,
+
+  /// Contains the command to send to the running instance, e.g.
+  /// `["queue", "spotify:track:..."]`.
+  Ctl(Vec<String>),
+
+  /// Forget the credentials libspotify remembers from the last sign in.
+  Logout,
+
+  /// Print a configuration file with the defaults and comments.
+  PrintDefaultConfig,
+
+  Help,
+  Version,
+}
+
+/// The command and options given on the command line.
+#[derive(Debug, PartialEq)]
+pub struct Arguments {
+  pub command: Command,
+
+  /// Read the configuration from this file instead of the default one.
+  pub config_path: Option<PathBuf>,
+
+  /// Sign in as this user instead of the one in the configuration.
+  pub username: Option<String>,
+
+  /// Don't take over the terminal, even without a command.
+  pub no_tui: bool,
+}
+
+/// Parses the command line arguments (without the name of the binary).
+/// Options can be given before or after the command.
+pub fn parse<I: Iterator<Item=String>>(args: I) -> Result<Arguments, String> {
+  let mut arguments = Arguments {
+    command: Command::Interface,
+    config_path: None,
+    username: None,
+    no_tui: false,
+  };
+
+  let mut flag_command: Option<Command> = None;
+  let mut positional: Vec<String> = Vec::new();
+  let mut args = args;
+
+  while let Some(argument) = args.next() {
+    match argument.as_str() {
+      "-h" | "--help" => flag_command = Some(Command::Help),
+      "-V" | "--version" => flag_command = Some(Command::Version),
+      "--logout" => flag_command = Some(Command::Logout),
+      "--print-default-config" => flag_command = Some(Command::PrintDefaultConfig),
+      "--no-tui" => arguments.no_tui = true,
+      "--config" => {
+        let path = try!(args.next().ok_or("--config needs the path of a configuration file".to_string()));
+        arguments.config_path = Some(PathBuf::from(path));
+      },
+      "--username" => {
+        arguments.username = Some(try!(args.next().ok_or("--username needs a username".to_string())));
+      },
+      _ if argument.starts_with("--config=") => {
+        arguments.config_path = Some(PathBuf::from(&argument["--config=".len()..]));
+      },
+      _ if argument.starts_with("--username=") => {
+        arguments.username = Some(argument["--username=".len()..].to_string());
+      },
+      // Negative numbers (e.g. `ctl seek -10`) aren't options
+      _ if argument.starts_with("-") && argument.parse::<i64>().is_err() => {
+        return Err(format!("Unknown option: {}", argument));
+      },
+      _ => positional.push(argument),
+    }
+  }
+
+  // --help and friends are handled before (and instead of) any command
+  if let Some(command) = flag_command {
+    arguments.command = command;
+    return Ok(arguments);
+  }
+
+  arguments.command = match positional.split_first() {
+    None => Command::Interface,
+    Some((name, rest)) => {
+      match name.as_str() {
+        "play" if rest.len() == 1 => Command::Play(rest[0].clone()),
+        "play" => return Err("Usage: neubauten play <uri>".to_string()),
+        "search" if !rest.is_empty() => Command::Search(rest.join(" ")),
+        "search" => return Err("Usage: neubauten search <query>".to_string()),
+        "playlists" if rest.is_empty() => Command::Playlists,
+        "playlists" => return Err("Usage: neubauten playlists".to_string()),
+        "ctl" if !rest.is_empty() => Command::Ctl(rest.to_vec()),
+        "ctl" => return Err("Usage: neubauten ctl <command>".to_string()),
+        _ => return Err(format!("Unknown command: {}", name)),
+      }
+    },
+  };
+
+  return Ok(arguments);
+}
+
+/// Plays the track with the given URI, returning when it has ended.
+pub fn play(session: &mut rustify::Session,
+            receiver: &std::sync::mpsc::Receiver<rustify::Event>,
+            uri: &str) -> Result<(), String> {
+  let track = try!(session.track_from_uri(uri).ok_or(format!("{} is not a Spotify track", uri)));
+
+  session.play_track(&track);
+  println!("Playing {}", track);
+
+  for event in receiver.iter() {
+    if let rustify::Event::EndOfTrack = event {
+      break;
+    }
+  }
+
+  return Ok(());
+}
+
+/// Prints the URI and name of every track matching the `query`, one per line
+/// so that it's easy to pick one for `play`.
+pub fn search(session: &rustify::Session, query: &str) {
+  for track in session.search(query).tracks() {
+    println!("{}\t{}", track.uri(), track);
+  }
+}
+
+/// Prints the playlists the same way as they're shown in the interface, with
+/// the contents of folders indented.
+pub fn print_playlists(entries: Vec<playlist_tree::Entry<rustify::Playlist>>) {
+  for row in playlist_tree::PlaylistTree::new(entries).rows() {
+    println!("{}", row);
+  }
+}
+
+/// Keeps the session running until it stops (or the process is killed).
+pub fn keep_running(receiver: &std::sync::mpsc::Receiver<rustify::Event>) {
+  for _ in receiver.iter() {}
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::path::{PathBuf};
+
+  fn parse_str(args: &str) -> Result<Arguments, String> {
+    return parse(args.split_whitespace().map(|arg| arg.to_string()));
+  }
+
+  #[test]
+  fn it_starts_the_interface_without_arguments() {
+    let arguments = parse_str("").unwrap();
+
+    assert_eq!(arguments.command, Command::Interface);
+    assert!(!arguments.no_tui);
+  }
+
+  #[test]
+  fn it_parses_options_around_the_command() {
+    let arguments = parse_str("--config /tmp/init.toml search --username=blixa neubauten tabula rasa").unwrap();
+
+    assert_eq!(arguments.command, Command::Search("neubauten tabula rasa".to_string()));
+    assert_eq!(arguments.config_path, Some(PathBuf::from("/tmp/init.toml")));
+    assert_eq!(arguments.username, Some("blixa".to_string()));
+  }
+
+  #[test]
+  fn it_prefers_help_over_the_command() {
+    assert_eq!(parse_str("playlists --help").unwrap().command, Command::Help);
+  }
+
+  #[test]
+  fn it_rejects_commands_with_missing_or_extra_arguments() {
+    assert!(parse_str("play").is_err());
+    assert!(parse_str("play spotify:track:1 spotify:track:2").is_err());
+    assert!(parse_str("playlists all").is_err());
+    assert!(parse_str("stop").is_err());
+    assert!(parse_str("--verbose").is_err());
+  }
+
+  #[test]
+  fn it_takes_negative_numbers_as_arguments() {
+    assert_eq!(parse_str("ctl seek -10").unwrap().command, Command::Ctl(vec!["seek".to_string(), "-10".to_string()]));
+  }
+}
@@ -1,4 +1,7 @@
 use action;
+use history::{History};
+use line_editor::{LineEditor};
+use plays;
 use rustbox;
 
 #[derive(Debug, PartialEq)]
@@ -21,24 +24,136 @@ pub struct CommandParser {
   /// This variable keeps track if we need to read input until we hit either:
   /// An `Esc` event which would clear the `input_sequence` and return `NoMatch` or
   /// A `Enter` event which would return the parsed event and clear the input sequence
-  argument: Vec<char>,
+  argument: LineEditor,
   argument_type: ArgumentType,
+
+  filter_history: History,
+  search_history: History,
+  command_history: History,
+
+  /// Set while doing a reverse incremental search (`Ctrl-R`) through the
+  /// history of the current `argument_type`.
+  reverse_search: Option<ReverseSearch>,
+
+  /// A question waiting for a yes or no, together with the action that will
+  /// be returned if the answer is yes.
+  confirmation: Option<(String, action::Action)>,
+
+  /// Key sequences bound in the configuration, matched before the default
+  /// ones.
+  key_bindings: Vec<(String, action::Action)>,
+}
+
+struct ReverseSearch {
+  query: Vec<char>,
+
+  /// Index of the history entry matching the `query`, if any.
+  match_index: Option<usize>,
+
+  /// The `argument` from before the search started, restored if the search
+  /// is cancelled.
+  original_argument: String,
 }
 
 #[derive(Clone, PartialEq)]
 pub enum ArgumentType {
   FilterArgument,
   SearchArgument,
+  CommandArgument,
   None,
 }
 
 impl CommandParser {
+  #[cfg(test)]
   pub fn new() -> Self {
+    return CommandParser::with_histories(History::new(), History::new(), History::new());
+  }
+
+  pub fn with_histories(filter_history: History,
+                        search_history: History,
+                        command_history: History) -> Self {
     return CommandParser {
       input_sequence: Vec::new(),
-      argument: Vec::new(),
+      argument: LineEditor::new(),
       argument_type: ArgumentType::None,
+      filter_history: filter_history,
+      search_history: search_history,
+      command_history: command_history,
+      reverse_search: None,
+      confirmation: None,
+      key_bindings: Vec::new(),
+    };
+  }
+
+  pub fn set_key_bindings(&mut self, key_bindings: Vec<(String, action::Action)>) {
+    self.key_bindings = key_bindings;
+  }
+
+  /// Returns the history for the `argument_type` we're reading.
+  fn current_history(&mut self) -> Option<&mut History> {
+    return match self.argument_type {
+      ArgumentType::FilterArgument => Some(&mut self.filter_history),
+      ArgumentType::SearchArgument => Some(&mut self.search_history),
+      ArgumentType::CommandArgument => Some(&mut self.command_history),
+      ArgumentType::None => None,
+    }
+  }
+
+  /// Updates the `reverse_search` with the given key.
+  ///
+  /// Returns whether or not the key should also be handled as usual, which is
+  /// the case for every key that ends the search by accepting the match.
+  fn update_reverse_search(&mut self, key: rustbox::Key) -> bool {
+    let mut search = self.reverse_search.take().unwrap();
+
+    // Where to start looking for a match, if we need to look for one
+    let search_from: Option<usize> = match key {
+      rustbox::Key::Char(c) => {
+        search.query.push(c);
+        Some(search.match_index.unwrap_or(usize::max_value()))
+      },
+      rustbox::Key::Backspace => {
+        search.query.pop();
+        Some(usize::max_value())
+      },
+      rustbox::Key::Ctrl('r') => {
+        match search.match_index {
+          Some(0) => None,
+          Some(index) => Some(index - 1),
+          None => Some(usize::max_value()),
+        }
+      },
+      rustbox::Key::Esc | rustbox::Key::Ctrl('g') => {
+        self.argument.set_value(&search.original_argument);
+        return false;
+      },
+      _ => return true,
     };
+
+    if let Some(from) = search_from {
+      let query: String = search.query.iter().cloned().collect();
+      let found = match self.current_history() {
+        Some(history) => history.search_backwards(&query, from).and_then(|index| {
+          history.get(index).map(|entry| (index, entry))
+        }),
+        None => None,
+      };
+
+      match found {
+        Some((index, entry)) => {
+          search.match_index = Some(index);
+          self.argument.set_value(&entry);
+        },
+        None => {
+          if key != rustbox::Key::Ctrl('r') {
+            search.match_index = None;
+          }
+        },
+      }
+    }
+
+    self.reverse_search = Some(search);
+    return false;
   }
 
   /// Takes an `rustbox::Event` and updates the internal `argument` value.
@@ -47,29 +162,117 @@ impl CommandParser {
   fn update_argument(&mut self, key_event: &rustbox::Event) -> bool {
     return match key_event {
       &rustbox::Event::KeyEvent(key) => {
+        if self.reverse_search.is_some() && !self.update_reverse_search(key) {
+          return false;
+        }
+
         match key {
           rustbox::Key::Enter => {
+            let argument = self.get_argument();
+            if let Some(history) = self.current_history() {
+              history.push(&argument);
+            }
+
             self.argument_type = ArgumentType::None;
             self.argument.clear();
             return true;
           },
           rustbox::Key::Esc => {
+            if let Some(history) = self.current_history() {
+              history.reset();
+            }
+
             self.argument_type = ArgumentType::None;
+            self.argument.clear();
             self.input_sequence.clear();
             return true;
           },
           rustbox::Key::Backspace => {
-            if self.argument.len() > 0 {
-              self.argument.pop();
+            if !self.argument.is_empty() {
+              self.argument.backspace();
               return false;
             } else {
+              if let Some(history) = self.current_history() {
+                history.reset();
+              }
+
               self.argument_type = ArgumentType::None;
               self.argument.clear();
               return false;
             }
           },
+          rustbox::Key::Up => {
+            let argument = self.get_argument();
+            let entry = match self.current_history() {
+              Some(history) => history.previous(&argument),
+              None => None,
+            };
+
+            if let Some(entry) = entry {
+              self.argument.set_value(&entry);
+            }
+
+            return false;
+          },
+          rustbox::Key::Down => {
+            let entry = match self.current_history() {
+              Some(history) => history.next(),
+              None => None,
+            };
+
+            if let Some(entry) = entry {
+              self.argument.set_value(&entry);
+            }
+
+            return false;
+          },
+          rustbox::Key::Ctrl('r') => {
+            self.reverse_search = Some(ReverseSearch {
+              query: Vec::new(),
+              match_index: None,
+              original_argument: self.argument.value(),
+            });
+
+            return false;
+          },
           rustbox::Key::Char(c) => {
-            self.argument.push(c);
+            self.argument.insert_char(c);
+            return false;
+          },
+          rustbox::Key::Delete | rustbox::Key::Ctrl('d') => {
+            self.argument.delete();
+            return false;
+          },
+          rustbox::Key::Left | rustbox::Key::Ctrl('b') => {
+            self.argument.move_left();
+            return false;
+          },
+          rustbox::Key::Right | rustbox::Key::Ctrl('f') => {
+            self.argument.move_right();
+            return false;
+          },
+          rustbox::Key::Home | rustbox::Key::Ctrl('a') => {
+            self.argument.move_home();
+            return false;
+          },
+          rustbox::Key::End | rustbox::Key::Ctrl('e') => {
+            self.argument.move_end();
+            return false;
+          },
+          rustbox::Key::Ctrl('w') => {
+            self.argument.kill_word_backward();
+            return false;
+          },
+          rustbox::Key::Ctrl('u') => {
+            self.argument.kill_to_start();
+            return false;
+          },
+          rustbox::Key::Ctrl('k') => {
+            self.argument.kill_to_end();
+            return false;
+          },
+          rustbox::Key::Ctrl('y') => {
+            self.argument.yank();
             return false;
           },
           _ => false
@@ -84,12 +287,48 @@ impl CommandParser {
   }
 
   pub fn get_argument(&self) -> String {
-    return self.argument.iter().cloned().collect();
+    return self.argument.value();
+  }
+
+  /// Returns the column (in terminal cells) of the cursor within the `argument`.
+  pub fn get_argument_cursor(&self) -> usize {
+    return self.argument.cursor_column();
+  }
+
+  /// Returns the query of the ongoing reverse incremental search, if any.
+  pub fn get_reverse_search_query(&self) -> Option<String> {
+    return self.reverse_search.as_ref().map(|search| search.query.iter().cloned().collect());
+  }
+
+  /// Returns whether the ongoing reverse incremental search has found an
+  /// entry matching its query.
+  pub fn has_reverse_search_match(&self) -> bool {
+    return match self.reverse_search {
+      Some(ref search) => search.query.is_empty() || search.match_index.is_some(),
+      None => false,
+    }
+  }
+
+  /// Asks the user the given `question`. If the next key is `y` the `action`
+  /// is returned from `handle_input`, any other key cancels.
+  pub fn confirm(&mut self, question: String, action: action::Action) {
+    self.confirmation = Some((question, action));
+  }
+
+  pub fn get_confirmation_question(&self) -> Option<String> {
+    return self.confirmation.as_ref().map(|&(ref question, _)| question.clone());
   }
 
   pub fn handle_input(&mut self, key_event: &rustbox::Event) -> Action {
+    if let Some((_, action)) = self.confirmation.take() {
+      return match key_event {
+        &rustbox::Event::KeyEvent(rustbox::Key::Char('y')) => Action::NAction(action),
+        _ => Action::NoMatch,
+      }
+    }
+
     if self.argument_type != ArgumentType::None {
-      let argument: String = self.argument.iter().cloned().collect();
+      let argument: String = self.argument.value();
       let argument_type = self.argument_type.clone();
 
       if self.update_argument(key_event) {
@@ -100,6 +339,12 @@ impl CommandParser {
           ArgumentType::SearchArgument => {
             Action::NAction(action::Action::SearchTrack(argument))
           },
+          ArgumentType::CommandArgument => {
+            match key_event {
+              &rustbox::Event::KeyEvent(rustbox::Key::Esc) => Action::NoMatch,
+              _ => Action::NAction(parse_command(&argument)),
+            }
+          },
           _ => Action::NoMatch,
         };
       } else {
@@ -117,6 +362,10 @@ impl CommandParser {
               self.argument_type = ArgumentType::SearchArgument;
               return Action::NoMatch;
             },
+            rustbox::Key::Char(':') => {
+              self.argument_type = ArgumentType::CommandArgument;
+              return Action::NoMatch;
+            },
             rustbox::Key::Char(c) => {
               self.input_sequence.push(c);
             },
@@ -142,16 +391,31 @@ impl CommandParser {
   }
 
   fn parse_input_sequence(&mut self) -> Action {
-    let registered_commands: Vec<(String, action::Action)> = vec![
+    let mut registered_commands: Vec<(String, action::Action)> = self.key_bindings.clone();
+    registered_commands.extend(vec![
       ("gg".to_string(), action::Action::MoveTop),
+      ("ga".to_string(), action::Action::GoToAlbum),
+      ("gr".to_string(), action::Action::GoToArtist),
       ("j".to_string(), action::Action::MoveDown),
       ("k".to_string(), action::Action::MoveUp),
       ("G".to_string(), action::Action::MoveBottom),
       ("q".to_string(), action::Action::QueueTrack),
+      ("Q".to_string(), action::Action::QueueAll),
+      ("p".to_string(), action::Action::QueueNext),
+      ("R".to_string(), action::Action::ReplaceQueue),
+      ("V".to_string(), action::Action::ToggleVisual),
+      ("m".to_string(), action::Action::ToggleMark),
+      ("*".to_string(), action::Action::MarkAll),
+      ("~".to_string(), action::Action::InvertMarks),
+      ("a".to_string(), action::Action::AddToPlaylist),
+      ("dd".to_string(), action::Action::RemoveTrack),
+      ("J".to_string(), action::Action::MoveTrackDown),
+      ("K".to_string(), action::Action::MoveTrackUp),
       ("e".to_string(), action::Action::Quit),
       (" ".to_string(), action::Action::TogglePlayback),
       (">".to_string(), action::Action::PlayNextTrack),
-    ];
+      ("<".to_string(), action::Action::PlayPreviousTrack),
+    ]);
 
     let mut found_partial_match = false;
     let mut exact_match: Option<action::Action> = None;
@@ -180,10 +444,50 @@ impl CommandParser {
   }
 }
 
+/// Parses a command entered after `:` (e.g. `new Playlist name`) into the
+/// action it should run.
+fn parse_command(command: &str) -> action::Action {
+  let command = command.trim();
+  let (name, argument) = match command.find(' ') {
+    Some(index) => (&command[..index], command[index..].trim()),
+    None => (command, ""),
+  };
+
+  return match name {
+    "new" | "newfolder" | "rename" | "savequeue" | "savequeue!" if argument.is_empty() => {
+      action::Action::InvalidCommand(format!("Usage: :{} <name>", name))
+    },
+    "new" => action::Action::CreatePlaylist(argument.to_string()),
+    "newfolder" => action::Action::CreateFolder(argument.to_string()),
+    "rename" => action::Action::RenamePlaylist(argument.to_string()),
+    "delete" => action::Action::DeletePlaylist,
+    "reload" => action::Action::ReloadConfig,
+    "stats" => {
+      match plays::Period::from_name(if argument.is_empty() { "week" } else { argument }) {
+        Some(period) => action::Action::ShowStats(period),
+        None => action::Action::InvalidCommand("Usage: :stats [week|month|all]".to_string()),
+      }
+    },
+    "sort" if argument.is_empty() => action::Action::SortList(None),
+    "sort" => {
+      match plays::SortKey::from_name(argument) {
+        Some(key) => action::Action::SortList(Some(key)),
+        None => action::Action::InvalidCommand("Usage: :sort [plays|last_played]".to_string()),
+      }
+    },
+    "unplayed" => action::Action::ShowUnplayed,
+    "savequeue" => action::Action::SaveQueue(argument.to_string(), false),
+    "savequeue!" => action::Action::SaveQueue(argument.to_string(), true),
+    "quit" | "q" => action::Action::Quit,
+    _ => action::Action::InvalidCommand(format!("Unknown command: {}", name)),
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
   use action;
+  use plays;
   use rustbox;
 
   #[test]
@@ -249,4 +553,213 @@ mod tests {
 
     assert_eq!(parser.handle_input(&key_event), Action::NAction(action::Action::Select));
   }
+
+  #[test]
+  fn it_prefers_the_configured_key_bindings() {
+    let mut parser = CommandParser::new();
+    parser.set_key_bindings(vec![("q".to_string(), action::Action::Quit)]);
+
+    let action = type_keys(&mut parser, vec![rustbox::Key::Char('q')]);
+
+    assert_eq!(action, Action::NAction(action::Action::Quit));
+  }
+
+  fn type_keys(parser: &mut CommandParser, keys: Vec<rustbox::Key>) -> Action {
+    let mut result = Action::NoMatch;
+    for key in keys {
+      result = parser.handle_input(&rustbox::Event::KeyEvent(key));
+    }
+
+    return result;
+  }
+
+  fn type_search(parser: &mut CommandParser, query: &str) -> Action {
+    let mut keys = vec![rustbox::Key::Char('s')];
+    keys.extend(query.chars().map(|c| rustbox::Key::Char(c)));
+    keys.push(rustbox::Key::Enter);
+
+    return type_keys(parser, keys);
+  }
+
+  #[test]
+  fn it_recalls_previous_searches_with_up() {
+    let mut parser = CommandParser::new();
+    type_search(&mut parser, "foo");
+    type_search(&mut parser, "bar");
+
+    let result = type_keys(&mut parser, vec![
+      rustbox::Key::Char('s'),
+      rustbox::Key::Up,
+      rustbox::Key::Up,
+      rustbox::Key::Enter,
+    ]);
+
+    assert_eq!(result, Action::NAction(action::Action::SearchTrack("foo".to_string())));
+  }
+
+  #[test]
+  fn it_keeps_a_separate_history_for_filters() {
+    let mut parser = CommandParser::new();
+    type_search(&mut parser, "foo");
+
+    type_keys(&mut parser, vec![rustbox::Key::Char('/'), rustbox::Key::Up]);
+
+    assert_eq!(parser.get_argument(), "");
+  }
+
+  #[test]
+  fn it_finds_searches_with_reverse_incremental_search() {
+    let mut parser = CommandParser::new();
+    type_search(&mut parser, "einstürzende");
+    type_search(&mut parser, "neubauten");
+    type_search(&mut parser, "bauhaus");
+
+    type_keys(&mut parser, vec![
+      rustbox::Key::Char('s'),
+      rustbox::Key::Ctrl('r'),
+      rustbox::Key::Char('n'),
+    ]);
+    assert_eq!(parser.get_argument(), "neubauten");
+
+    let result = type_keys(&mut parser, vec![rustbox::Key::Ctrl('r'), rustbox::Key::Enter]);
+    assert_eq!(result, Action::NAction(action::Action::SearchTrack("einstürzende".to_string())));
+  }
+
+  #[test]
+  fn it_restores_the_argument_when_cancelling_a_reverse_search() {
+    let mut parser = CommandParser::new();
+    type_search(&mut parser, "neubauten");
+
+    type_keys(&mut parser, vec![
+      rustbox::Key::Char('s'),
+      rustbox::Key::Char('x'),
+      rustbox::Key::Ctrl('r'),
+      rustbox::Key::Char('n'),
+      rustbox::Key::Esc,
+    ]);
+
+    assert_eq!(parser.get_argument(), "x");
+    assert_eq!(parser.get_reverse_search_query(), None);
+  }
+
+  #[test]
+  fn it_edits_the_argument_at_the_cursor() {
+    let mut parser = CommandParser::new();
+
+    let result = type_keys(&mut parser, vec![
+      rustbox::Key::Char('s'),
+      rustbox::Key::Char('b'),
+      rustbox::Key::Char('a'),
+      rustbox::Key::Char('r'),
+      rustbox::Key::Home,
+      rustbox::Key::Delete,
+      rustbox::Key::Char('f'),
+      rustbox::Key::End,
+      rustbox::Key::Left,
+      rustbox::Key::Backspace,
+      rustbox::Key::Char('o'),
+      rustbox::Key::Enter,
+    ]);
+
+    assert_eq!(result, Action::NAction(action::Action::SearchTrack("for".to_string())));
+  }
+
+  #[test]
+  fn it_returns_the_confirmed_action_on_y() {
+    let mut parser = CommandParser::new();
+    parser.confirm("Sure?".to_string(), action::Action::RemoveTrack);
+
+    let result = type_keys(&mut parser, vec![rustbox::Key::Char('y')]);
+
+    assert_eq!(result, Action::NAction(action::Action::RemoveTrack));
+    assert_eq!(parser.get_confirmation_question(), None);
+  }
+
+  #[test]
+  fn it_cancels_the_confirmation_on_any_other_key() {
+    let mut parser = CommandParser::new();
+    parser.confirm("Sure?".to_string(), action::Action::RemoveTrack);
+
+    let result = type_keys(&mut parser, vec![rustbox::Key::Char('j')]);
+
+    assert_eq!(result, Action::NoMatch);
+    assert_eq!(parser.get_confirmation_question(), None);
+  }
+
+  fn type_command(parser: &mut CommandParser, command: &str) -> Action {
+    let mut keys = vec![rustbox::Key::Char(':')];
+    keys.extend(command.chars().map(|c| rustbox::Key::Char(c)));
+    keys.push(rustbox::Key::Enter);
+
+    return type_keys(parser, keys);
+  }
+
+  #[test]
+  fn it_parses_commands_with_an_argument() {
+    let mut parser = CommandParser::new();
+
+    let result = type_command(&mut parser, "new Kollaps  ");
+
+    assert_eq!(result, Action::NAction(action::Action::CreatePlaylist("Kollaps".to_string())));
+  }
+
+  #[test]
+  fn it_reports_unknown_commands() {
+    let mut parser = CommandParser::new();
+
+    let result = type_command(&mut parser, "halber mensch");
+
+    assert_eq!(result, Action::NAction(action::Action::InvalidCommand("Unknown command: halber".to_string())));
+  }
+
+  #[test]
+  fn it_does_not_run_the_command_on_esc() {
+    let mut parser = CommandParser::new();
+
+    let result = type_keys(&mut parser, vec![
+      rustbox::Key::Char(':'),
+      rustbox::Key::Char('q'),
+      rustbox::Key::Esc,
+    ]);
+
+    assert_eq!(result, Action::NoMatch);
+  }
+
+  #[test]
+  fn it_parses_savequeue_with_and_without_the_current_track() {
+    let mut parser = CommandParser::new();
+
+    assert_eq!(type_command(&mut parser, "savequeue Tabula rasa"),
+               Action::NAction(action::Action::SaveQueue("Tabula rasa".to_string(), false)));
+    assert_eq!(type_command(&mut parser, "savequeue! Tabula rasa"),
+               Action::NAction(action::Action::SaveQueue("Tabula rasa".to_string(), true)));
+  }
+
+  #[test]
+  fn it_parses_reload() {
+    let mut parser = CommandParser::new();
+
+    assert_eq!(type_command(&mut parser, "reload"), Action::NAction(action::Action::ReloadConfig));
+  }
+
+  #[test]
+  fn it_parses_stats_with_an_optional_period() {
+    let mut parser = CommandParser::new();
+
+    assert_eq!(type_command(&mut parser, "stats"), Action::NAction(action::Action::ShowStats(plays::Period::Week)));
+    assert_eq!(type_command(&mut parser, "stats all"), Action::NAction(action::Action::ShowStats(plays::Period::AllTime)));
+    assert_eq!(type_command(&mut parser, "stats year"),
+               Action::NAction(action::Action::InvalidCommand("Usage: :stats [week|month|all]".to_string())));
+  }
+
+  #[test]
+  fn it_parses_sort_with_an_optional_key() {
+    let mut parser = CommandParser::new();
+
+    assert_eq!(type_command(&mut parser, "sort last_played"),
+               Action::NAction(action::Action::SortList(Some(plays::SortKey::LastPlayed))));
+    assert_eq!(type_command(&mut parser, "sort"), Action::NAction(action::Action::SortList(None)));
+    assert_eq!(type_command(&mut parser, "sort title"),
+               Action::NAction(action::Action::InvalidCommand("Usage: :sort [plays|last_played]".to_string())));
+  }
 }
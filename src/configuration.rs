@@ -1,36 +1,1320 @@
+use action;
+use libc;
+use rustbox::{Color};
+use rustify;
+use status_file;
 use std;
-use std::io::Read;
-use rustc_serialize::json;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use rustc_serialize::json::{self, Json};
+use toml;
+
+/// The environment variable that can be used for giving the password.
+const PASSWORD_VARIABLE: &'static str = "NEUBAUTEN_PASSWORD";
+
+/// The environment variable that can be used for giving the path of the
+/// configuration file.
+const CONFIG_VARIABLE: &'static str = "NEUBAUTEN_CONFIG";
+
+/// A configuration file with every setting at its default and comments
+/// explaining them, printed by `--print-default-config`.
+pub const DEFAULT_CONFIG: &'static str = include_str!("default_config.toml");
+
+/// The formats the configuration file can be written in.
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+  Toml,
+
+  /// Still accepted for configuration files from before TOML was supported.
+  Json,
+}
+
+impl Format {
+  /// Files ending with `.json` are JSON, anything else is TOML.
+  fn of(path: &Path) -> Format {
+    return match path.extension() {
+      Some(extension) if extension == "json" => Format::Json,
+      _ => Format::Toml,
+    }
+  }
+}
+
+/// Everything that can be configured in the configuration file, with the
+/// defaults filled in for anything that isn't.
+pub struct Config {
+  pub username: Option<String>,
+
+  /// The password from wherever it was found (see `load`). Anything missing
+  /// will have to be asked for.
+  pub password: Option<String>,
+
+  /// Key sequences bound to actions, which take precedence over the default
+  /// bindings.
+  pub keys: Vec<(String, action::Action)>,
+
+  pub theme: Theme,
+
+  /// Where libspotify keeps its cache.
+  pub cache_path: PathBuf,
+
+  /// Where libspotify keeps its settings (e.g. the remembered credentials).
+  pub settings_path: PathBuf,
+
+  pub playback: Playback,
+
+  pub lists: ListSettings,
+
+  /// Whether or not to reload the configuration when the file changes.
+  pub watch: bool,
+
+  /// Where to listen for MPD clients, if at all.
+  pub mpd_address: Option<String>,
+
+  pub hooks: HookCommands,
+
+  pub status_file: StatusFileSettings,
+
+  pub notifications: NotificationSettings,
+
+  pub scrobbling: ScrobbleSettings,
+
+  /// The configuration file this was read from.
+  path: PathBuf,
+
+  /// Where the history is kept.
+  data_dir: PathBuf,
+
+  /// A command (e.g. `pass show spotify`) which prints the password.
+  password_command: Option<String>,
+
+  /// A file containing the password, which only the user may read.
+  password_file: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy)]
+pub struct Style {
+  pub foreground: Color,
+  pub background: Color,
+}
+
+#[derive(Clone, Copy)]
+pub struct Theme {
+  /// The row under the cursor in lists.
+  pub cursor: Style,
+
+  /// Marked rows in lists.
+  pub marked: Style,
+
+  pub status_bar: Style,
+}
+
+impl Default for Theme {
+  fn default() -> Self {
+    return Theme {
+      cursor: Style { foreground: Color::White, background: Color::Black },
+      marked: Style { foreground: Color::Yellow, background: Color::Default },
+      status_bar: Style { foreground: Color::White, background: Color::Cyan },
+    };
+  }
+}
+
+pub struct Playback {
+  pub bitrate: rustify::Bitrate,
+  pub volume_normalization: bool,
+}
+
+impl Default for Playback {
+  fn default() -> Self {
+    return Playback {
+      bitrate: rustify::Bitrate::Bitrate160k,
+      volume_normalization: false,
+    };
+  }
+}
+
+/// The extra columns shown in front of the tracks in lists.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct ListSettings {
+  /// How many times the track has been played (not counting skips).
+  pub play_count: bool,
+
+  /// How long ago the track was last played.
+  pub last_played: bool,
+}
+
+/// Commands run (with `sh -c`) when something happens to the playback.
+#[derive(Clone)]
+pub struct HookCommands {
+  /// When a track starts playing.
+  pub on_track_change: Option<String>,
+
+  pub on_pause: Option<String>,
+  pub on_resume: Option<String>,
+
+  /// When the playback stops since there's nothing left to play.
+  pub on_queue_empty: Option<String>,
+
+  /// How long a command may run before it's killed.
+  pub timeout: std::time::Duration,
+}
+
+impl Default for HookCommands {
+  fn default() -> Self {
+    return HookCommands {
+      on_track_change: None,
+      on_pause: None,
+      on_resume: None,
+      on_queue_empty: None,
+      timeout: std::time::Duration::from_secs(10),
+    };
+  }
+}
+
+/// Where to write what's playing for status bars, and how.
+pub struct StatusFileSettings {
+  /// A file or named pipe. Nothing is written without one.
+  pub path: Option<PathBuf>,
+
+  /// Fields like `{title}` are filled in (see `status_file::format_status`).
+  pub format: String,
+}
+
+impl Default for StatusFileSettings {
+  fn default() -> Self {
+    return StatusFileSettings {
+      path: None,
+      format: "{track} [{elapsed}/{duration}]{paused}".to_string(),
+    };
+  }
+}
+
+/// Desktop notifications shown when a track starts playing.
+pub struct NotificationSettings {
+  pub enabled: bool,
+
+  /// Fields describing the track, like `{title}` (see
+  /// `status_file::format_track`).
+  pub title: String,
+  pub body: String,
+}
+
+impl Default for NotificationSettings {
+  fn default() -> Self {
+    return NotificationSettings {
+      enabled: false,
+      title: "{title}".to_string(),
+      body: "{artist} - {album}".to_string(),
+    };
+  }
+}
+
+/// The services listens are submitted to, if any.
+#[derive(Clone, Default, PartialEq)]
+pub struct ScrobbleSettings {
+  pub lastfm: Option<LastFmSettings>,
+  pub listenbrainz: Option<ListenBrainzSettings>,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct LastFmSettings {
+  /// The key and secret of an API account on Last.fm.
+  pub api_key: String,
+  pub api_secret: String,
 
-#[derive(RustcDecodable)]
-pub struct Credentials {
   pub username: String,
-  pub password: String,
+  pub password: PasswordSource,
+
+  /// The API root, which can be changed to use a compatible service.
+  pub url: String,
+}
+
+/// Where a password is read from, when it's needed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PasswordSource {
+  /// The password itself, given in the configuration file.
+  Plain(String),
+
+  /// A command which prints the password.
+  Command(String),
+
+  /// A file containing the password, which only the user may read.
+  File(PathBuf),
 }
 
-pub fn root_dir() -> std::path::PathBuf {
-  let mut path = std::env::home_dir().unwrap();
-  path.push(".config/neubauten/");
-  return path;
+impl PasswordSource {
+  pub fn read(&self) -> Result<String, ConfigError> {
+    return match self {
+      &PasswordSource::Plain(ref password) => Ok(password.clone()),
+      &PasswordSource::Command(ref command) => run_password_command(command),
+      &PasswordSource::File(ref path) => read_password_file(path),
+    }
+  }
 }
 
-pub fn spotify_path() -> String {
-  let mut path = std::env::home_dir().unwrap();
-  path.push(".config/neubauten/tmp/");
-  return String::from(path.to_str().unwrap());
+#[derive(Clone, PartialEq)]
+pub struct ListenBrainzSettings {
+  /// The user token from the profile page on ListenBrainz.
+  pub token: String,
+
+  /// The API root, which can be changed to use another instance.
+  pub url: String,
 }
 
-pub fn read_credentials() -> Credentials {
-  let mut configuration_path = root_dir();
-  configuration_path.push("init.json");
-  let path = configuration_path.to_str().unwrap();
+#[derive(Debug)]
+pub enum ConfigError {
+  /// `$HOME` isn't set, so there's nowhere to look for the configuration.
+  NoHomeDirectory,
+
+  Io(PathBuf, std::io::Error),
+
+  /// The file isn't valid TOML (or JSON).
+  Syntax {
+    path: PathBuf,
+    line: usize,
+    column: usize,
+    message: String,
+  },
 
-  match std::fs::File::open(path) {
+  /// A key which isn't a setting, e.g. because of a typo.
+  UnknownKey {
+    path: PathBuf,
+    key: String,
+    position: Option<(usize, usize)>,
+  },
+
+  /// A setting with a value that can't be used, e.g. a string where a
+  /// number was expected.
+  InvalidValue {
+    path: PathBuf,
+    key: String,
+    position: Option<(usize, usize)>,
+    message: String,
+  },
+
+  /// The password couldn't be read from the `password_command` or
+  /// `password_file`.
+  Password(String),
+}
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    return match self {
+      &ConfigError::NoHomeDirectory => {
+        write!(f, "Unable to find the configuration, $HOME is not set")
+      },
+      &ConfigError::Io(ref path, ref e) => write!(f, "Unable to read {}: {}", path.display(), e),
+      &ConfigError::Syntax { ref path, line, column, ref message } => {
+        write!(f, "{}:{}:{}: {}", path.display(), line, column, message)
+      },
+      &ConfigError::UnknownKey { ref path, ref key, position } => {
+        write!(f, "{}: unknown setting `{}`", location(path, position), key)
+      },
+      &ConfigError::InvalidValue { ref path, ref key, position, ref message } => {
+        write!(f, "{}: invalid value for `{}`: {}", location(path, position), key, message)
+      },
+      &ConfigError::Password(ref message) => write!(f, "{}", message),
+    }
+  }
+}
+
+fn location(path: &Path, position: Option<(usize, usize)>) -> String {
+  return match position {
+    Some((line, column)) => format!("{}:{}:{}", path.display(), line, column),
+    None => format!("{}", path.display()),
+  }
+}
+
+fn home_dir() -> Result<PathBuf, ConfigError> {
+  return std::env::home_dir().ok_or(ConfigError::NoHomeDirectory);
+}
+
+/// Returns the `neubauten` directory inside of the XDG base directory given
+/// by `variable`, or inside of `fallback` (relative to the home directory) if
+/// the variable isn't set. Relative paths are ignored, like the XDG Base
+/// Directory Specification says.
+fn base_dir(variable: &str, fallback: &str) -> Result<PathBuf, ConfigError> {
+  let base = match std::env::var_os(variable) {
+    Some(ref dir) if Path::new(dir).is_absolute() => PathBuf::from(dir),
+    _ => try!(home_dir()).join(fallback),
+  };
+
+  return Ok(base.join("neubauten"));
+}
+
+/// `$XDG_CONFIG_HOME/neubauten`, where the configuration file is kept.
+pub fn config_dir() -> Result<PathBuf, ConfigError> {
+  return base_dir("XDG_CONFIG_HOME", ".config");
+}
+
+/// `$XDG_CACHE_HOME/neubauten`, where libspotify keeps its cache.
+pub fn cache_dir() -> Result<PathBuf, ConfigError> {
+  return base_dir("XDG_CACHE_HOME", ".cache");
+}
+
+/// `$XDG_DATA_HOME/neubauten`, where the history and libspotify's settings
+/// are kept.
+pub fn data_dir() -> Result<PathBuf, ConfigError> {
+  return base_dir("XDG_DATA_HOME", ".local/share");
+}
+
+/// The control socket (see `control::ControlSocket`), in
+/// `$XDG_RUNTIME_DIR` or the temporary directory if that isn't set.
+pub fn socket_path() -> PathBuf {
+  return match std::env::var_os("XDG_RUNTIME_DIR") {
+    Some(ref dir) if Path::new(dir).is_absolute() => Path::new(dir).join("neubauten.sock"),
+    _ => std::env::temp_dir().join(format!("neubauten-{}.sock", unsafe { libc::getuid() })),
+  }
+}
+
+/// Reads the configuration from the given file, the file in the
+/// `NEUBAUTEN_CONFIG` environment variable or `init.toml` (or `init.json`)
+/// in the configuration directory (in that order). The directories we use
+/// are created if they don't exist. The password is only looked up by
+/// `Config::resolve_password`, when it's needed for signing in.
+///
+/// A missing `init.toml` is not an error, the defaults are used and we'll ask
+/// for the credentials instead. A file that has been given explicitly has to
+/// exist though.
+pub fn load(path: Option<PathBuf>) -> Result<Config, ConfigError> {
+  let (path, explicit) = match (path, std::env::var_os(CONFIG_VARIABLE)) {
+    (Some(path), _) => (path, true),
+    (None, Some(ref path)) if !path.is_empty() => (PathBuf::from(path), true),
+    (None, _) => (try!(default_path()), false),
+  };
+
+  migrate_old_files(&try!(home_dir()).join(".config/neubauten"), &try!(data_dir()));
+
+  return read(path, explicit);
+}
+
+/// Moves the files earlier versions kept in `~/.config/neubauten/` to the
+/// data directory, unless they already exist there. Anything that can't be
+/// moved (e.g. because it's on another file system) is left where it was.
+fn migrate_old_files(old_dir: &Path, data_dir: &Path) {
+  let names = [
+    // libspotify's settings, including the remembered credentials
+    ("tmp", "spotify"),
+    ("filter_history", "filter_history"),
+    ("search_history", "search_history"),
+    ("command_history", "command_history"),
+  ];
+
+  for &(old_name, new_name) in names.iter() {
+    let (old_path, new_path) = (old_dir.join(old_name), data_dir.join(new_name));
+
+    if old_path.exists() && !new_path.exists() {
+      let _ = std::fs::create_dir_all(data_dir).and_then(|_| std::fs::rename(&old_path, &new_path));
+    }
+  }
+}
+
+/// Reads the configuration file at `path`, without looking for the password.
+fn read(path: PathBuf, explicit: bool) -> Result<Config, ConfigError> {
+  let defaults = Config::defaults(&path, &try!(cache_dir()), &try!(data_dir()));
+
+  let config = match std::fs::File::open(&path) {
     Ok(mut file) => {
       let mut data = String::new();
-      file.read_to_string(&mut data).unwrap();
-      return json::decode(&data).unwrap();
+      if let Err(e) = file.read_to_string(&mut data) {
+        return Err(ConfigError::Io(path, e));
+      }
+
+      try!(parse(&data, defaults))
     },
-    Err(_) => panic!("No configuration file found, aborting..."),
+    Err(ref e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => defaults,
+    Err(e) => return Err(ConfigError::Io(path, e)),
+  };
+
+  let mut dirs = vec![config.cache_path.clone(), config.settings_path.clone(), config.data_dir.clone()];
+  if let Some(parent) = config.path.parent() {
+    dirs.push(parent.to_path_buf());
+  }
+
+  for dir in dirs {
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+      return Err(ConfigError::Io(dir, e));
+    }
+  }
+
+  return Ok(config);
+}
+
+/// Returns `init.toml` in the configuration directory, unless there only is
+/// an `init.json`.
+fn default_path() -> Result<PathBuf, ConfigError> {
+  let dir = try!(config_dir());
+  let json_path = dir.join("init.json");
+  let toml_path = dir.join("init.toml");
+
+  if json_path.exists() && !toml_path.exists() {
+    return Ok(json_path);
+  } else {
+    return Ok(toml_path);
+  }
+}
+
+impl Config {
+  fn defaults(path: &Path, cache_dir: &Path, data_dir: &Path) -> Self {
+    return Config {
+      username: None,
+      password: None,
+      keys: Vec::new(),
+      theme: Theme::default(),
+      cache_path: cache_dir.to_path_buf(),
+      settings_path: data_dir.join("spotify"),
+      playback: Playback::default(),
+      lists: ListSettings::default(),
+      watch: false,
+      mpd_address: None,
+      hooks: HookCommands::default(),
+      status_file: StatusFileSettings::default(),
+      notifications: NotificationSettings::default(),
+      scrobbling: ScrobbleSettings::default(),
+      path: path.to_path_buf(),
+      data_dir: data_dir.to_path_buf(),
+      password_command: None,
+      password_file: None,
+    };
+  }
+
+  pub fn path(&self) -> &Path {
+    return &self.path;
+  }
+
+  /// Looks up the password to sign in with. It's taken from (in order) the
+  /// `NEUBAUTEN_PASSWORD` environment variable, the output of
+  /// `password_command`, the contents of `password_file` or `password`.
+  ///
+  /// This is kept apart from `load` since the `password_command` may ask for
+  /// a passphrase, which e.g. `--logout` shouldn't.
+  pub fn resolve_password(&mut self) -> Result<(), ConfigError> {
+    if let Ok(password) = std::env::var(PASSWORD_VARIABLE) {
+      self.password = Some(password);
+    } else if let Some(ref command) = self.password_command {
+      self.password = Some(try!(run_password_command(command)));
+    } else if let Some(ref password_file) = self.password_file {
+      self.password = Some(try!(read_password_file(password_file)));
+    }
+
+    return Ok(());
+  }
+
+  /// Reads the configuration file again, e.g. after it has been changed.
+  /// Since we're already signed in the credentials are kept as they are.
+  ///
+  /// Unlike when starting, a missing file is an error so that the settings
+  /// aren't reset while an editor is replacing the file.
+  pub fn reload(&self) -> Result<Config, ConfigError> {
+    let mut config = try!(read(self.path.clone(), true));
+    config.username = self.username.clone();
+    config.password = self.password.clone();
+
+    return Ok(config);
+  }
+
+  /// Returns the path of the file where the history of the given kind (e.g.
+  /// `search`) is stored.
+  pub fn history_path(&self, kind: &str) -> PathBuf {
+    return self.data_dir.join(format!("{}_history", kind));
+  }
+
+  /// Returns the path of the file where the listens that couldn't be
+  /// submitted to the `service` yet are kept.
+  pub fn scrobble_queue_path(&self, service: &str) -> PathBuf {
+    return self.data_dir.join(format!("{}_queue", service));
+  }
+
+  /// Returns the path of the log of every track that has been played.
+  pub fn play_log_path(&self) -> PathBuf {
+    return self.data_dir.join("plays");
+  }
+
+  /// Returns warnings about how the credentials are stored, which are shown
+  /// to the user while signing in.
+  pub fn credential_warnings(&self) -> Vec<String> {
+    let path = &self.path;
+    let mut warnings: Vec<String> = Vec::new();
+
+    if let Ok(metadata) = std::fs::metadata(&path) {
+      if metadata.permissions().mode() & 0o004 != 0 {
+        warnings.push(format!("{} can be read by anyone, run `chmod 600 {}`", path.display(), path.display()));
+      }
+    }
+
+    return warnings;
+  }
+
+  /// Creates a configuration file containing only the `username` (the
+  /// password is never stored), unless there already is a configuration file.
+  pub fn save_username(&self, username: &str) {
+    let file = std::fs::OpenOptions::new()
+      .write(true)
+      .create_new(true)
+      .mode(0o600)
+      .open(&self.path);
+
+    if let Ok(mut file) = file {
+      // A JSON string is a valid TOML string as well
+      let username = json::encode(&username).unwrap();
+
+      let _ = match Format::of(&self.path) {
+        Format::Toml => writeln!(file, "username = {}", username),
+        Format::Json => writeln!(file, "{{\n  \"username\": {}\n}}", username),
+      };
+    }
+  }
+}
+
+/// Keeps track of when the configuration file was last changed, so that it
+/// can be reloaded when it changes.
+pub struct ConfigWatcher {
+  path: PathBuf,
+  modified: Option<std::time::SystemTime>,
+  checked_at: std::time::Instant,
+}
+
+impl ConfigWatcher {
+  pub fn new(config: &Config) -> Self {
+    return ConfigWatcher {
+      path: config.path.clone(),
+      modified: modified_at(&config.path),
+      checked_at: std::time::Instant::now(),
+    };
+  }
+
+  /// Returns whether or not the file has been changed since the last time
+  /// this returned `true` (or since the watcher was created). The file is
+  /// looked at no more than once a second since this is called for every
+  /// turn of the event loop.
+  pub fn has_changed(&mut self) -> bool {
+    if self.checked_at.elapsed() < std::time::Duration::from_secs(1) {
+      return false;
+    }
+
+    self.checked_at = std::time::Instant::now();
+
+    let modified = modified_at(&self.path);
+    if modified != self.modified {
+      self.modified = modified;
+      return true;
+    } else {
+      return false;
+    }
+  }
+}
+
+fn modified_at(path: &Path) -> Option<std::time::SystemTime> {
+  return std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+}
+
+/// Parses the contents of the configuration file on top of the `defaults`.
+/// Every key is checked so that typos are reported instead of silently
+/// ignored.
+fn parse(data: &str, defaults: Config) -> Result<Config, ConfigError> {
+  let path = defaults.path.clone();
+  let format = Format::of(&path);
+
+  let json = match format {
+    Format::Toml => try!(parse_toml(&path, data)),
+    Format::Json => try!(parse_json(&path, data)),
+  };
+
+  let parser = Parser { path: &path, data: data, format: format };
+  let mut config = defaults;
+
+  for (key, value) in try!(parser.object("", &json)) {
+    match key.as_str() {
+      "username" => config.username = Some(try!(parser.string(key, value))),
+      "password" => config.password = Some(try!(parser.string(key, value))),
+      "password_command" => config.password_command = Some(try!(parser.string(key, value))),
+      "password_file" => config.password_file = Some(try!(expand_home(&try!(parser.string(key, value))))),
+      "watch_config" => config.watch = try!(parser.boolean(key, value)),
+      "cache_path" => config.cache_path = try!(expand_home(&try!(parser.string(key, value)))),
+      "keys" => {
+        for (sequence, name) in try!(parser.object(key, value)) {
+          let key = format!("keys.{}", sequence);
+          let name = try!(parser.string(&key, name));
+
+          match action::from_name(&name) {
+            Some(action) => config.keys.push((sequence.clone(), action)),
+            None => return Err(parser.invalid_value(&key, format!("unknown action `{}`", name))),
+          }
+        }
+      },
+      "theme" => {
+        for (element, style) in try!(parser.object(key, value)) {
+          let key = format!("theme.{}", element);
+          let current = match element.as_str() {
+            "cursor" => &mut config.theme.cursor,
+            "marked" => &mut config.theme.marked,
+            "status_bar" => &mut config.theme.status_bar,
+            _ => return Err(parser.unknown_key(&key)),
+          };
+
+          try!(parser.style(&key, style, current));
+        }
+      },
+      "playback" => {
+        for (option, value) in try!(parser.object(key, value)) {
+          let key = format!("playback.{}", option);
+
+          match option.as_str() {
+            "bitrate" => {
+              config.playback.bitrate = match value.as_u64() {
+                Some(96) => rustify::Bitrate::Bitrate96k,
+                Some(160) => rustify::Bitrate::Bitrate160k,
+                Some(320) => rustify::Bitrate::Bitrate320k,
+                _ => return Err(parser.invalid_value(&key, "expected 96, 160 or 320".to_string())),
+              };
+            },
+            "volume_normalization" => {
+              config.playback.volume_normalization = try!(parser.boolean(&key, value));
+            },
+            _ => return Err(parser.unknown_key(&key)),
+          }
+        }
+      },
+      "lists" => {
+        for (option, value) in try!(parser.object(key, value)) {
+          let key = format!("lists.{}", option);
+
+          match option.as_str() {
+            "play_count" => config.lists.play_count = try!(parser.boolean(&key, value)),
+            "last_played" => config.lists.last_played = try!(parser.boolean(&key, value)),
+            _ => return Err(parser.unknown_key(&key)),
+          }
+        }
+      },
+      "status_file" => {
+        for (option, value) in try!(parser.object(key, value)) {
+          let key = format!("status_file.{}", option);
+
+          match option.as_str() {
+            "path" => config.status_file.path = Some(try!(expand_home(&try!(parser.string(&key, value))))),
+            "format" => config.status_file.format = try!(parser.format(&key, value)),
+            _ => return Err(parser.unknown_key(&key)),
+          }
+        }
+      },
+      "notifications" => {
+        for (option, value) in try!(parser.object(key, value)) {
+          let key = format!("notifications.{}", option);
+
+          match option.as_str() {
+            "enabled" => config.notifications.enabled = try!(parser.boolean(&key, value)),
+            "title" => config.notifications.title = try!(parser.track_format(&key, value)),
+            "body" => config.notifications.body = try!(parser.track_format(&key, value)),
+            _ => return Err(parser.unknown_key(&key)),
+          }
+        }
+      },
+      "scrobbling" => {
+        for (service, value) in try!(parser.object(key, value)) {
+          let key = format!("scrobbling.{}", service);
+
+          match service.as_str() {
+            "lastfm" => {
+              let mut fields = try!(parser.strings(&key, value, &["api_key", "api_secret", "username"],
+                                                   &["password", "password_command", "password_file", "url"]));
+
+              // Like for Spotify, the password doesn't have to be written in
+              // the file
+              let password = match (fields.remove("password"), fields.remove("password_command"), fields.remove("password_file")) {
+                (Some(password), None, None) => PasswordSource::Plain(password),
+                (None, Some(command), None) => PasswordSource::Command(command),
+                (None, None, Some(path)) => PasswordSource::File(try!(expand_home(&path))),
+                _ => {
+                  let message = "expected one of `password`, `password_command` or `password_file`".to_string();
+                  return Err(parser.invalid_value(&key, message));
+                },
+              };
+
+              config.scrobbling.lastfm = Some(LastFmSettings {
+                api_key: fields.remove("api_key").unwrap(),
+                api_secret: fields.remove("api_secret").unwrap(),
+                username: fields.remove("username").unwrap(),
+                password: password,
+                url: fields.remove("url").unwrap_or("https://ws.audioscrobbler.com/2.0/".to_string()),
+              });
+            },
+            "listenbrainz" => {
+              let mut fields = try!(parser.strings(&key, value, &["token"], &["url"]));
+              config.scrobbling.listenbrainz = Some(ListenBrainzSettings {
+                token: fields.remove("token").unwrap(),
+                url: fields.remove("url").unwrap_or("https://api.listenbrainz.org".to_string()),
+              });
+            },
+            _ => return Err(parser.unknown_key(&key)),
+          }
+        }
+      },
+      "mpd" => {
+        for (option, value) in try!(parser.object(key, value)) {
+          let key = format!("mpd.{}", option);
+
+          match option.as_str() {
+            "address" => config.mpd_address = Some(try!(parser.string(&key, value))),
+            _ => return Err(parser.unknown_key(&key)),
+          }
+        }
+      },
+      "hooks" => {
+        for (option, value) in try!(parser.object(key, value)) {
+          let key = format!("hooks.{}", option);
+
+          match option.as_str() {
+            "on_track_change" => config.hooks.on_track_change = Some(try!(parser.string(&key, value))),
+            "on_pause" => config.hooks.on_pause = Some(try!(parser.string(&key, value))),
+            "on_resume" => config.hooks.on_resume = Some(try!(parser.string(&key, value))),
+            "on_queue_empty" => config.hooks.on_queue_empty = Some(try!(parser.string(&key, value))),
+            "timeout" => {
+              config.hooks.timeout = match value.as_u64() {
+                Some(seconds) if seconds > 0 => std::time::Duration::from_secs(seconds),
+                _ => return Err(parser.invalid_value(&key, "expected a number of seconds".to_string())),
+              };
+            },
+            _ => return Err(parser.unknown_key(&key)),
+          }
+        }
+      },
+      _ => return Err(parser.unknown_key(key)),
+    }
+  }
+
+  return Ok(config);
+}
+
+fn parse_json(path: &Path, data: &str) -> Result<Json, ConfigError> {
+  return match Json::from_str(data) {
+    Ok(json) => Ok(json),
+    Err(json::ParserError::SyntaxError(code, line, column)) => {
+      Err(ConfigError::Syntax {
+        path: path.to_path_buf(),
+        line: line,
+        column: column,
+        message: json::error_str(code).to_string(),
+      })
+    },
+    Err(json::ParserError::IoError(e)) => Err(ConfigError::Io(path.to_path_buf(), e)),
+  }
+}
+
+/// Parses TOML into the same kind of value as JSON is parsed into, so that
+/// the settings are read the same way whatever the format.
+fn parse_toml(path: &Path, data: &str) -> Result<Json, ConfigError> {
+  let mut parser = toml::Parser::new(data);
+
+  return match parser.parse() {
+    Some(table) => Ok(toml_to_json(toml::Value::Table(table))),
+    None => {
+      let (message, (line, column)) = match parser.errors.first() {
+        Some(error) => (error.desc.clone(), parser.to_linecol(error.lo)),
+        None => ("invalid TOML".to_string(), (0, 0)),
+      };
+
+      Err(ConfigError::Syntax {
+        path: path.to_path_buf(),
+        line: line + 1,
+        column: column + 1,
+        message: message,
+      })
+    },
+  }
+}
+
+fn toml_to_json(value: toml::Value) -> Json {
+  return match value {
+    toml::Value::String(string) => Json::String(string),
+    toml::Value::Integer(integer) => Json::I64(integer),
+    toml::Value::Float(float) => Json::F64(float),
+    toml::Value::Boolean(boolean) => Json::Boolean(boolean),
+    toml::Value::Datetime(datetime) => Json::String(datetime),
+    toml::Value::Array(values) => Json::Array(values.into_iter().map(toml_to_json).collect()),
+    toml::Value::Table(table) => {
+      Json::Object(table.into_iter().map(|(key, value)| (key, toml_to_json(value))).collect())
+    },
+  }
+}
+
+/// Helpers for reading the values of the configuration, turning anything
+/// unexpected into errors pointing at the offending key.
+struct Parser<'a> {
+  path: &'a Path,
+  data: &'a str,
+  format: Format,
+}
+
+impl<'a> Parser<'a> {
+  fn object<'b>(&self, key: &str, value: &'b Json) -> Result<&'b BTreeMap<String, Json>, ConfigError> {
+    return match value {
+      &Json::Object(ref object) => Ok(object),
+      _ => Err(self.invalid_value(key, "expected an object".to_string())),
+    }
+  }
+
+  fn string(&self, key: &str, value: &Json) -> Result<String, ConfigError> {
+    return match value {
+      &Json::String(ref string) => Ok(string.clone()),
+      _ => Err(self.invalid_value(key, "expected a string".to_string())),
+    }
+  }
+
+  /// A table of strings, which has to contain the `required` keys and may
+  /// contain the `optional` ones.
+  fn strings(&self,
+             key: &str,
+             value: &Json,
+             required: &[&str],
+             optional: &[&str]) -> Result<BTreeMap<String, String>, ConfigError> {
+    let mut strings: BTreeMap<String, String> = BTreeMap::new();
+
+    for (name, value) in try!(self.object(key, value)) {
+      let key = format!("{}.{}", key, name);
+      if !required.contains(&name.as_str()) && !optional.contains(&name.as_str()) {
+        return Err(self.unknown_key(&key));
+      }
+
+      strings.insert(name.clone(), try!(self.string(&key, value)));
+    }
+
+    for name in required {
+      if !strings.contains_key(*name) {
+        return Err(self.invalid_value(key, format!("missing `{}`", name)));
+      }
+    }
+
+    return Ok(strings);
+  }
+
+  /// A string with fields like `{title}` (see `status_file::format_status`).
+  fn format(&self, key: &str, value: &Json) -> Result<String, ConfigError> {
+    let format = try!(self.string(key, value));
+
+    return match status_file::check_format(&format) {
+      Ok(()) => Ok(format),
+      Err(e) => Err(self.invalid_value(key, e)),
+    }
+  }
+
+  /// Like `format`, but only with the fields describing the track (see
+  /// `status_file::format_track`).
+  fn track_format(&self, key: &str, value: &Json) -> Result<String, ConfigError> {
+    let format = try!(self.string(key, value));
+
+    return match status_file::check_track_format(&format) {
+      Ok(()) => Ok(format),
+      Err(e) => Err(self.invalid_value(key, e)),
+    }
+  }
+
+  fn boolean(&self, key: &str, value: &Json) -> Result<bool, ConfigError> {
+    return match value {
+      &Json::Boolean(boolean) => Ok(boolean),
+      _ => Err(self.invalid_value(key, "expected true or false".to_string())),
+    }
+  }
+
+  /// Reads the `foreground` and/or `background` of a style, keeping the
+  /// current colors for anything left out.
+  fn style(&self, key: &str, value: &Json, style: &mut Style) -> Result<(), ConfigError> {
+    for (part, color) in try!(self.object(key, value)) {
+      let key = format!("{}.{}", key, part);
+      let color = match color_from_name(&try!(self.string(&key, color))) {
+        Some(color) => color,
+        None => return Err(self.invalid_value(&key, "expected a color like `default`, `red` or `cyan`".to_string())),
+      };
+
+      match part.as_str() {
+        "foreground" => style.foreground = color,
+        "background" => style.background = color,
+        _ => return Err(self.unknown_key(&key)),
+      }
+    }
+
+    return Ok(());
+  }
+
+  fn unknown_key(&self, key: &str) -> ConfigError {
+    return ConfigError::UnknownKey {
+      path: self.path.to_path_buf(),
+      key: key.to_string(),
+      position: self.find_key(key),
+    };
+  }
+
+  fn invalid_value(&self, key: &str, message: String) -> ConfigError {
+    return ConfigError::InvalidValue {
+      path: self.path.to_path_buf(),
+      key: key.to_string(),
+      position: self.find_key(key),
+      message: message,
+    };
+  }
+
+  /// Finds the line and column of a (dotted) key in the configuration file,
+  /// since the parsed values don't keep track of where they came from.
+  fn find_key(&self, key: &str) -> Option<(usize, usize)> {
+    return match self.format {
+      Format::Toml => find_toml_key(self.data, key),
+      Format::Json => find_json_key(self.data, key),
+    }
+  }
+}
+
+/// The parts of the key are searched for one after another so that e.g.
+/// `theme.cursor` isn't mistaken for another `cursor`.
+fn find_json_key(data: &str, key: &str) -> Option<(usize, usize)> {
+  let mut offset = 0;
+
+  for part in key.split('.') {
+    let quoted = json::encode(&part).unwrap();
+    let mut found: Option<usize> = None;
+
+    for (index, _) in data[offset..].match_indices(&*quoted) {
+      let after = &data[(offset + index + quoted.len())..];
+      if after.trim_left().starts_with(':') {
+        found = Some(offset + index);
+        break;
+      }
+    }
+
+    offset = match found {
+      Some(offset) => offset,
+      None => return None,
+    };
+  }
+
+  let line = data[..offset].matches('\n').count() + 1;
+  let column = data[..offset].chars().rev().take_while(|&c| c != '\n').count() + 1;
+
+  return Some((line, column));
+}
+
+/// Looks for the `[table]` header or the `key = ` line whose full key (i.e.
+/// together with the table it's in) is the given key.
+fn find_toml_key(data: &str, key: &str) -> Option<(usize, usize)> {
+  let mut table = String::new();
+
+  for (number, line) in data.lines().enumerate() {
+    let column = line.len() - line.trim_left().len() + 1;
+    let line = line.trim();
+
+    if line.starts_with('#') {
+      continue;
+    } else if line.starts_with('[') {
+      let parts: Vec<String> = line.trim_matches(|c| c == '[' || c == ']')
+        .split('.')
+        .map(|part| unquote(part.trim()))
+        .collect();
+      table = parts.join(".");
+
+      if table == key {
+        return Some((number + 1, column));
+      }
+    } else if let Some(index) = line.find('=') {
+      let name = unquote(line[..index].trim());
+      let full_key = if table.is_empty() { name } else { format!("{}.{}", table, name) };
+
+      if full_key == key {
+        return Some((number + 1, column));
+      }
+    }
+  }
+
+  return None;
+}
+
+fn unquote(key: &str) -> String {
+  return key.trim_matches('"').to_string();
+}
+
+/// Expands a leading `~/` into the home directory.
+fn expand_home(path: &str) -> Result<PathBuf, ConfigError> {
+  if path.starts_with("~/") {
+    return Ok(try!(home_dir()).join(&path[2..]));
+  } else {
+    return Ok(PathBuf::from(path));
+  }
+}
+
+fn color_from_name(name: &str) -> Option<Color> {
+  return match name {
+    "default" => Some(Color::Default),
+    "black" => Some(Color::Black),
+    "red" => Some(Color::Red),
+    "green" => Some(Color::Green),
+    "yellow" => Some(Color::Yellow),
+    "blue" => Some(Color::Blue),
+    "magenta" => Some(Color::Magenta),
+    "cyan" => Some(Color::Cyan),
+    "white" => Some(Color::White),
+    _ => None,
+  }
+}
+
+fn run_password_command(command: &str) -> Result<String, ConfigError> {
+  let output = std::process::Command::new("sh").arg("-c").arg(command).output();
+
+  return match output {
+    Ok(ref output) if output.status.success() => {
+      // Tools like `pass` print the password on the first line
+      let stdout = String::from_utf8_lossy(&output.stdout);
+      Ok(stdout.lines().next().unwrap_or("").to_string())
+    },
+    Ok(output) => {
+      Err(ConfigError::Password(format!("password_command `{}` failed: {}", command, String::from_utf8_lossy(&output.stderr).trim())))
+    },
+    Err(e) => Err(ConfigError::Password(format!("Unable to run password_command `{}`: {}", command, e))),
+  }
+}
+
+fn read_password_file(path: &Path) -> Result<String, ConfigError> {
+  let metadata = match std::fs::metadata(path) {
+    Ok(metadata) => metadata,
+    Err(e) => return Err(ConfigError::Password(format!("Unable to read password_file {}: {}", path.display(), e))),
+  };
+
+  // Same as ssh does with private keys, refuse to use a password which other
+  // users can read
+  if metadata.permissions().mode() & 0o077 != 0 {
+    return Err(ConfigError::Password(format!("password_file {} can be read by other users, run `chmod 600 {}`", path.display(), path.display())));
+  }
+
+  let mut data = String::new();
+  return match std::fs::File::open(path).and_then(|mut file| file.read_to_string(&mut data)) {
+    Ok(_) => Ok(data.lines().next().unwrap_or("").to_string()),
+    Err(e) => Err(ConfigError::Password(format!("Unable to read password_file {}: {}", path.display(), e))),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use action;
+  use rustbox::{Color};
+  use std::path::{Path};
+  use test_fixtures;
+
+  fn parse_file(name: &str, data: &str) -> Result<Config, ConfigError> {
+    let defaults = Config::defaults(&Path::new("/").join(name), Path::new("/cache"), Path::new("/data"));
+    return parse(data, defaults);
+  }
+
+  fn parse_str(data: &str) -> Result<Config, ConfigError> {
+    return parse_file("init.json", data);
+  }
+
+  #[test]
+  fn it_uses_the_defaults_for_anything_missing() {
+    let config = parse_str("{ \"username\": \"blixa\" }").unwrap();
+
+    assert_eq!(config.username, Some("blixa".to_string()));
+    assert_eq!(config.password, None);
+    assert!(config.keys.is_empty());
+    assert!(config.theme.status_bar.background == Color::Cyan);
+    assert_eq!(config.cache_path, Path::new("/cache").to_path_buf());
+    assert_eq!(config.history_path("search"), Path::new("/data/search_history").to_path_buf());
+    assert!(!config.playback.volume_normalization);
+  }
+
+  #[test]
+  fn it_expands_the_home_directory_in_the_password_file() {
+    let config = parse_file("init.toml", "password_file = \"~/.spotify-password\"").unwrap();
+    let home = std::env::home_dir().unwrap();
+
+    assert_eq!(config.password_file, Some(home.join(".spotify-password")));
+  }
+
+  #[test]
+  fn it_only_runs_the_password_command_when_resolving_the_password() {
+    let mut config = parse_str("{ \"password_command\": \"echo einstürzen\" }").unwrap();
+    assert_eq!(config.password, None);
+
+    config.resolve_password().unwrap();
+    assert_eq!(config.password, Some("einstürzen".to_string()));
+  }
+
+  #[test]
+  fn it_moves_the_files_from_the_old_directory() {
+    let dir = test_fixtures::temporary_path("migrate");
+    let (old_dir, data_dir) = (dir.join("old"), dir.join("data"));
+
+    std::fs::create_dir_all(old_dir.join("tmp")).unwrap();
+    std::fs::File::create(old_dir.join("tmp/settings")).unwrap();
+    std::fs::File::create(old_dir.join("search_history")).unwrap();
+    std::fs::create_dir_all(&data_dir).unwrap();
+    std::fs::File::create(data_dir.join("search_history")).unwrap();
+
+    migrate_old_files(&old_dir, &data_dir);
+
+    assert!(data_dir.join("spotify/settings").exists() && !old_dir.join("tmp").exists());
+    assert!(old_dir.join("search_history").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn it_reads_keys_and_themes() {
+    let config = parse_str(r#"{
+      "keys": { "x": "queue_track" },
+      "theme": { "cursor": { "background": "blue" } }
+    }"#).unwrap();
+
+    assert_eq!(config.keys, vec![("x".to_string(), action::Action::QueueTrack)]);
+    assert!(config.theme.cursor.background == Color::Blue);
+    assert!(config.theme.cursor.foreground == Color::White);
+  }
+
+  #[test]
+  fn it_reports_where_the_syntax_error_is() {
+    let error = parse_str("{\n  \"username\": \"blixa\"\n  \"password\": \"\"\n}").err().unwrap();
+
+    match error {
+      ConfigError::Syntax { line, .. } => assert_eq!(line, 3),
+      error => panic!("Unexpected error: {}", error),
+    }
+  }
+
+  #[test]
+  fn it_reports_unknown_keys() {
+    let error = parse_str("{\n  \"theme\": {\n    \"cursr\": {}\n  }\n}").err().unwrap();
+
+    assert_eq!(error.to_string(), "/init.json:3:5: unknown setting `theme.cursr`");
+  }
+
+  #[test]
+  fn it_reports_invalid_values() {
+    let error = parse_str("{ \"playback\": { \"bitrate\": \"high\" } }").err().unwrap();
+
+    assert_eq!(error.to_string(), "/init.json:1:17: invalid value for `playback.bitrate`: expected 96, 160 or 320");
+  }
+
+  #[test]
+  fn it_reads_sectioned_toml() {
+    let config = parse_file("init.toml", r#"
+      # Comments are allowed
+      username = "blixa"
+
+      [keys]
+      " " = "toggle_playback"
+
+      [theme.marked]
+      foreground = "red"
+    "#).unwrap();
+
+    assert_eq!(config.username, Some("blixa".to_string()));
+    assert_eq!(config.keys, vec![(" ".to_string(), action::Action::TogglePlayback)]);
+    assert!(config.theme.marked.foreground == Color::Red);
+  }
+
+  #[test]
+  fn it_reports_where_unknown_toml_keys_are() {
+    let error = parse_file("init.toml", "[playback]\nbitrate = 320\n\n[theme.cursor]\n  forground = \"red\"\n").err().unwrap();
+
+    assert_eq!(error.to_string(), "/init.toml:5:3: unknown setting `theme.cursor.forground`");
+  }
+
+  #[test]
+  fn it_has_the_defaults_in_the_default_config() {
+    let config = parse_file("init.toml", DEFAULT_CONFIG).unwrap();
+    let theme = Theme::default();
+
+    assert!(config.username.is_none() && config.password.is_none());
+    assert!(config.keys.is_empty());
+    assert!(config.theme.cursor.foreground == theme.cursor.foreground);
+    assert!(config.theme.cursor.background == theme.cursor.background);
+    assert!(config.theme.marked.foreground == theme.marked.foreground);
+    assert!(config.theme.marked.background == theme.marked.background);
+    assert!(config.theme.status_bar.foreground == theme.status_bar.foreground);
+    assert!(config.theme.status_bar.background == theme.status_bar.background);
+    assert!(!config.playback.volume_normalization);
+
+    match (config.playback.bitrate, Playback::default().bitrate) {
+      (rustify::Bitrate::Bitrate160k, rustify::Bitrate::Bitrate160k) => (),
+      _ => panic!("the default config doesn't have the default bitrate"),
+    }
+  }
+
+  #[test]
+  fn it_only_watches_when_asked_to() {
+    assert!(!parse_file("init.toml", "").unwrap().watch);
+    assert!(parse_file("init.toml", "watch_config = true").unwrap().watch);
+  }
+
+  #[test]
+  fn it_reads_the_hooks() {
+    let config = parse_file("init.toml", "[hooks]\non_pause = \"notify-send paused\"\ntimeout = 3").unwrap();
+
+    assert_eq!(config.hooks.on_pause, Some("notify-send paused".to_string()));
+    assert_eq!(config.hooks.on_resume, None);
+    assert_eq!(config.hooks.timeout, std::time::Duration::from_secs(3));
+    assert!(parse_file("init.toml", "[hooks]\ntimeout = 0").is_err());
+  }
+
+  #[test]
+  fn it_checks_the_status_file_format() {
+    let config = parse_file("init.toml", "[status_file]\npath = \"/tmp/playing\"\nformat = \"{title}\"").unwrap();
+
+    assert_eq!(config.status_file.path, Some(PathBuf::from("/tmp/playing")));
+    assert_eq!(config.status_file.format, "{title}");
+
+    let error = parse_file("init.toml", "[status_file]\nformat = \"{name}\"").err().unwrap();
+    assert!(error.to_string().contains("unknown field `{name}`"));
+  }
+
+  #[test]
+  fn it_reads_the_notification_templates() {
+    let config = parse_file("init.toml", "[notifications]\nenabled = true\nbody = \"{album}\"").unwrap();
+
+    assert!(config.notifications.enabled);
+    assert_eq!(config.notifications.title, "{title}");
+    assert_eq!(config.notifications.body, "{album}");
+    assert!(parse_file("init.toml", "[notifications]\ntitle = \"{year}\"").is_err());
+    assert!(parse_file("init.toml", "[notifications]\nbody = \"{elapsed}\"").is_err());
+  }
+
+  #[test]
+  fn it_reads_the_list_columns() {
+    let config = parse_file("init.toml", "[lists]\nplay_count = true").unwrap();
+
+    assert!(config.lists.play_count);
+    assert!(!config.lists.last_played);
+    assert!(parse_file("init.toml", "[lists]\nplays = true").is_err());
+  }
+
+  #[test]
+  fn it_reads_the_scrobbling_services() {
+    let config = parse_file("init.toml", "[scrobbling.listenbrainz]\ntoken = \"abc\"").unwrap();
+    let listenbrainz = config.scrobbling.listenbrainz.unwrap();
+
+    assert!(config.scrobbling.lastfm.is_none());
+    assert_eq!(listenbrainz.token, "abc");
+    assert_eq!(listenbrainz.url, "https://api.listenbrainz.org");
+
+    let error = parse_file("init.toml", "[scrobbling.lastfm]\napi_key = \"abc\"").err().unwrap();
+    assert!(error.to_string().contains("missing `api_secret`"));
+  }
+
+  #[test]
+  fn it_reads_where_the_lastfm_password_is() {
+    let lastfm = "[scrobbling.lastfm]\napi_key = \"abc\"\napi_secret = \"def\"\nusername = \"blixa\"\n";
+
+    let config = parse_file("init.toml", &format!("{}password_command = \"echo einstürzen\"", lastfm)).unwrap();
+    let password = config.scrobbling.lastfm.unwrap().password;
+    assert_eq!(password, PasswordSource::Command("echo einstürzen".to_string()));
+    assert_eq!(password.read().unwrap(), "einstürzen");
+
+    let error = parse_file("init.toml", lastfm).err().unwrap();
+    assert!(error.to_string().contains("expected one of `password`, `password_command` or `password_file`"));
+  }
+
+  #[test]
+  fn it_reads_the_mpd_address() {
+    assert_eq!(parse_file("init.toml", "").unwrap().mpd_address, None);
+    assert_eq!(parse_file("init.toml", "[mpd]\naddress = \"127.0.0.1:6600\"").unwrap().mpd_address,
+               Some("127.0.0.1:6600".to_string()));
   }
 }
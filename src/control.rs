@@ -0,0 +1,382 @@
+use action;
+use libc;
+use rustc_serialize::json::{Json};
+use rustify;
+use std;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Sender};
+use std::time::Duration;
+
+/// A command received from another program (e.g. on the control socket),
+/// waiting for the event loop to carry it out.
+pub struct Request {
+  pub action: action::Action,
+
+  /// Where to send the status once the action has been carried out.
+  pub reply: Sender<Status>,
+}
+
+/// A track, copied out of libspotify so that it can be sent to other threads.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackInfo {
+  pub uri: String,
+
+  /// How the track is shown in the interface.
+  pub label: String,
+
+  pub title: String,
+  pub artists: Vec<String>,
+  pub album: String,
+  pub duration: Duration,
+}
+
+impl TrackInfo {
+  pub fn new(track: &rustify::Track) -> TrackInfo {
+    return TrackInfo {
+      uri: track.uri(),
+      label: track.to_string(),
+      title: track.name(),
+      artists: track.artists().iter().map(|artist| artist.name()).collect(),
+      album: track.album().name(),
+      duration: track.duration(),
+    };
+  }
+}
+
+/// What the player is doing once a request has been carried out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Status {
+  pub track: Option<TrackInfo>,
+  pub playing: bool,
+
+  /// How far into the track the player is.
+  pub position: Duration,
+
+  /// The tracks that will be played after the current one.
+  pub queue: Vec<TrackInfo>,
+
+  /// The message shown in the interface, if any.
+  pub message: Option<String>,
+
+  /// The tracks found, when the request was a search.
+  pub search_results: Vec<TrackInfo>,
+
+  /// Why the action couldn't be carried out, if it failed.
+  pub error: Option<String>,
+}
+
+/// Listens for commands on a Unix domain socket. Each line written to the
+/// socket is a JSON object like `{"command": "toggle"}`, and is answered
+/// with a line containing the status (or an error) as JSON.
+///
+/// The socket is removed when this is dropped.
+pub struct ControlSocket {
+  path: PathBuf,
+}
+
+impl ControlSocket {
+  /// Starts listening on the socket at `path`, sending the commands to
+  /// `requests`. A socket left behind by an earlier instance is replaced, but
+  /// not one that's still being used.
+  pub fn listen(path: &Path, requests: Sender<Request>) -> Result<ControlSocket, String> {
+    if path.exists() {
+      if UnixStream::connect(path).is_ok() {
+        return Err(format!("{} is used by another instance", path.display()));
+      }
+
+      let _ = std::fs::remove_file(path);
+    }
+
+    // Only the user may control the player. The socket is created without
+    // permissions for anyone else (rather than changing them afterwards), so
+    // that nobody can connect in between.
+    let umask = unsafe { libc::umask(0o077) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(umask) };
+
+    let listener = match listener {
+      Ok(listener) => listener,
+      Err(e) => return Err(format!("Unable to listen on {}: {}", path.display(), e)),
+    };
+
+    std::thread::spawn(move || {
+      for stream in listener.incoming() {
+        if let Ok(stream) = stream {
+          let requests = requests.clone();
+          std::thread::spawn(move || handle_connection(stream, requests));
+        }
+      }
+    });
+
+    return Ok(ControlSocket { path: path.to_path_buf() });
+  }
+}
+
+impl Drop for ControlSocket {
+  fn drop(&mut self) {
+    let _ = std::fs::remove_file(&self.path);
+  }
+}
+
+fn handle_connection(stream: UnixStream, sender: Sender<Request>) {
+  let mut writer = match stream.try_clone() {
+    Ok(writer) => writer,
+    Err(_) => return,
+  };
+
+  for line in BufReader::new(stream).lines() {
+    let line = match line {
+      Ok(line) => line,
+      Err(_) => return,
+    };
+
+    if line.trim().is_empty() {
+      continue;
+    }
+
+    let response = match parse_request(&line) {
+      Ok(action) => {
+        let (reply, response) = std::sync::mpsc::channel();
+        if sender.send(Request { action: action, reply: reply }).is_err() {
+          return;
+        }
+
+        match response.recv() {
+          Ok(status) => status_json(&status),
+          Err(_) => return,
+        }
+      },
+      Err(e) => error(&e),
+    };
+
+    if writeln!(writer, "{}", response).is_err() {
+      return;
+    }
+  }
+}
+
+/// Turns a line received on the socket into the action to carry out. The
+/// `status` command doesn't do anything, since every command is answered
+/// with the status anyway.
+pub fn parse_request(line: &str) -> Result<action::Action, String> {
+  let json = match Json::from_str(line) {
+    Ok(json) => json,
+    Err(e) => return Err(format!("Invalid JSON: {}", e)),
+  };
+
+  let object = try!(json.as_object().ok_or("Expected an object".to_string()));
+  let command = try!(string_field(object, "command"));
+
+  return match command.as_str() {
+    "status" => Ok(action::Action::Noop),
+    "toggle" => Ok(action::Action::TogglePlayback),
+    "next" => Ok(action::Action::PlayNextTrack),
+    "previous" => Ok(action::Action::PlayPreviousTrack),
+    "seek" => {
+      match object.get("offset") {
+        Some(&Json::I64(offset)) => Ok(action::Action::Seek(offset)),
+        Some(&Json::U64(offset)) => Ok(action::Action::Seek(offset as i64)),
+        _ => Err("Expected a number \"offset\" (in milliseconds)".to_string()),
+      }
+    },
+    "queue" => Ok(action::Action::QueueUri(try!(string_field(object, "uri")))),
+    "search" => Ok(action::Action::FindTracks(try!(string_field(object, "query")))),
+    _ => Err(format!("Unknown command: {}", command)),
+  }
+}
+
+fn string_field(object: &BTreeMap<String, Json>, name: &str) -> Result<String, String> {
+  return match object.get(name) {
+    Some(&Json::String(ref value)) => Ok(value.clone()),
+    _ => Err(format!("Expected a string \"{}\"", name)),
+  }
+}
+
+/// Builds the request for the command given to `neubauten ctl`, e.g.
+/// `queue spotify:track:...`.
+pub fn request_from_args(args: &[String]) -> Result<String, String> {
+  let mut object: BTreeMap<String, Json> = BTreeMap::new();
+
+  let (command, rest) = try!(args.split_first().ok_or("Usage: neubauten ctl <command>".to_string()));
+  object.insert("command".to_string(), Json::String(command.clone()));
+
+  match command.as_str() {
+    "status" | "toggle" | "next" | "previous" if rest.is_empty() => (),
+    "seek" if rest.len() == 1 && rest[0].trim_left_matches('+').parse::<i64>().is_ok() => {
+      let seconds = rest[0].trim_left_matches('+').parse::<i64>().unwrap();
+      object.insert("offset".to_string(), Json::I64(seconds * 1000));
+    },
+    "queue" if rest.len() == 1 => {
+      object.insert("uri".to_string(), Json::String(rest[0].clone()));
+    },
+    "search" if !rest.is_empty() => {
+      object.insert("query".to_string(), Json::String(rest.join(" ")));
+    },
+    "seek" => return Err("Usage: neubauten ctl seek <seconds>, e.g. +30 or -10".to_string()),
+    "queue" => return Err("Usage: neubauten ctl queue <uri>".to_string()),
+    "search" => return Err("Usage: neubauten ctl search <query>".to_string()),
+    _ => return Err(format!("Unknown command: {}", args.join(" "))),
+  }
+
+  return Ok(Json::Object(object).to_string());
+}
+
+/// Sends the request to the running instance and returns its response.
+pub fn send(path: &Path, request: &str) -> Result<String, String> {
+  let mut stream = match UnixStream::connect(path) {
+    Ok(stream) => stream,
+    Err(e) => return Err(format!("Unable to connect to {} (is neubauten running?): {}", path.display(), e)),
+  };
+
+  if let Err(e) = writeln!(stream, "{}", request) {
+    return Err(format!("Unable to send the command: {}", e));
+  }
+
+  let mut response = String::new();
+  return match BufReader::new(stream).read_line(&mut response) {
+    Ok(0) => Err("neubauten quit before answering".to_string()),
+    Ok(_) => Ok(response.trim_right().to_string()),
+    Err(e) => Err(format!("Unable to read the response: {}", e)),
+  }
+}
+
+/// Returns whether or not the response is an error.
+pub fn is_error(response: &str) -> bool {
+  return match Json::from_str(response) {
+    Ok(json) => json.find("ok").and_then(|ok| ok.as_boolean()) != Some(true),
+    Err(_) => true,
+  }
+}
+
+/// The response to a command, telling what's playing (or why the command
+/// failed). The position is in milliseconds, like the offset of the `seek`
+/// command.
+fn status_json(status: &Status) -> String {
+  if let Some(ref message) = status.error {
+    return error(message);
+  }
+
+  let track = status.track.as_ref();
+  let position = status.position.as_secs() * 1000 + (status.position.subsec_nanos() / 1_000_000) as u64;
+  let mut object: BTreeMap<String, Json> = BTreeMap::new();
+
+  object.insert("ok".to_string(), Json::Boolean(true));
+  object.insert("playing".to_string(), Json::Boolean(track.is_some() && status.playing));
+  object.insert("track".to_string(), track.map_or(Json::Null, |track| Json::String(track.label.clone())));
+  object.insert("uri".to_string(), track.map_or(Json::Null, |track| Json::String(track.uri.clone())));
+  object.insert("position".to_string(), track.map_or(Json::Null, |_| Json::U64(position)));
+  object.insert("queue".to_string(), Json::U64(status.queue.len() as u64));
+  object.insert("message".to_string(), status.message.as_ref().map_or(Json::Null, |message| Json::String(message.clone())));
+  object.insert("search_results".to_string(), Json::Array(status.search_results.iter().map(|track| {
+    let mut result: BTreeMap<String, Json> = BTreeMap::new();
+    result.insert("track".to_string(), Json::String(track.label.clone()));
+    result.insert("uri".to_string(), Json::String(track.uri.clone()));
+    Json::Object(result)
+  }).collect()));
+
+  return Json::Object(object).to_string();
+}
+
+fn error(message: &str) -> String {
+  let mut object: BTreeMap<String, Json> = BTreeMap::new();
+
+  object.insert("ok".to_string(), Json::Boolean(false));
+  object.insert("error".to_string(), Json::String(message.to_string()));
+
+  return Json::Object(object).to_string();
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use action;
+  use std::os::unix::fs::{PermissionsExt};
+  use std::time::Duration;
+  use test_fixtures;
+
+  fn args(args: &str) -> Vec<String> {
+    return args.split_whitespace().map(|arg| arg.to_string()).collect();
+  }
+
+  #[test]
+  fn it_parses_requests_into_actions() {
+    assert_eq!(parse_request(r#"{"command": "toggle"}"#), Ok(action::Action::TogglePlayback));
+    assert_eq!(parse_request(r#"{"command": "queue", "uri": "spotify:track:1"}"#),
+               Ok(action::Action::QueueUri("spotify:track:1".to_string())));
+  }
+
+  #[test]
+  fn it_rejects_invalid_requests() {
+    assert!(parse_request("toggle").is_err());
+    assert!(parse_request(r#"{"command": "queue"}"#).is_err());
+    assert!(parse_request(r#"{"command": "stop"}"#).is_err());
+  }
+
+  #[test]
+  fn it_builds_requests_that_can_be_parsed() {
+    let request = request_from_args(&args("search tabula rasa")).unwrap();
+
+    assert_eq!(parse_request(&request), Ok(action::Action::FindTracks("tabula rasa".to_string())));
+    assert!(request_from_args(&args("next now")).is_err());
+
+    let request = request_from_args(&args("seek -10")).unwrap();
+    assert_eq!(parse_request(&request), Ok(action::Action::Seek(-10000)));
+    assert!(request_from_args(&args("seek later")).is_err());
+  }
+
+  #[test]
+  fn it_answers_errors_with_ok_false() {
+    assert!(is_error(&error("Unknown command: stop")));
+    let mut status = Status {
+      track: None,
+      playing: false,
+      position: Duration::from_secs(0),
+      queue: Vec::new(),
+      message: None,
+      search_results: Vec::new(),
+      error: None,
+    };
+
+    assert!(!is_error(&status_json(&status)));
+
+    status.error = Some("spotify:track:3 is not a Spotify track".to_string());
+    assert!(is_error(&status_json(&status)));
+  }
+
+  #[test]
+  fn it_answers_with_the_position_and_the_search_results() {
+    let track = test_fixtures::track("spotify:track:1", "Zebulon");
+    let status = Status {
+      track: Some(track.clone()),
+      playing: true,
+      position: Duration::from_millis(61500),
+      queue: Vec::new(),
+      message: None,
+      search_results: vec![track],
+      error: None,
+    };
+
+    let json = Json::from_str(&status_json(&status)).unwrap();
+    assert_eq!(json.find("position"), Some(&Json::U64(61500)));
+
+    let results = json.find("search_results").and_then(|results| results.as_array()).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].find("uri"), Some(&Json::String("spotify:track:1".to_string())));
+  }
+
+  #[test]
+  fn it_only_lets_the_user_connect_to_the_socket() {
+    let path = test_fixtures::temporary_path("control.sock");
+    let (sender, _) = std::sync::mpsc::channel();
+    let socket = ControlSocket::listen(&path, sender).unwrap();
+
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o077, 0);
+
+    drop(socket);
+    assert!(!path.exists());
+  }
+}
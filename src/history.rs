@@ -0,0 +1,215 @@
+use std;
+use std::io::{BufRead, Write};
+
+/// The maximum number of entries we keep around, both in memory and on disk.
+const MAX_ENTRIES: usize = 500;
+
+pub struct History {
+  /// All of the entries, oldest first.
+  entries: Vec<String>,
+
+  /// The index in `entries` of the entry currently being recalled. `None` when
+  /// we're not browsing through the history.
+  position: Option<usize>,
+
+  /// Whatever had been typed before we started browsing, so that we can
+  /// restore it when moving past the newest entry.
+  draft: String,
+
+  /// Where new entries gets appended. An in-memory history has no path.
+  path: Option<std::path::PathBuf>,
+}
+
+impl History {
+  pub fn new() -> Self {
+    return History {
+      entries: Vec::new(),
+      position: None,
+      draft: String::new(),
+      path: None,
+    };
+  }
+
+  /// Reads the history stored in the file at `path` (one entry per line). If
+  /// the file doesn't exist we start out with an empty history and create the
+  /// file once the first entry is added.
+  pub fn load(path: std::path::PathBuf) -> Self {
+    let mut history = History::new();
+
+    if let Ok(file) = std::fs::File::open(&path) {
+      for line in std::io::BufReader::new(file).lines() {
+        match line {
+          Ok(entry) => {
+            if !entry.is_empty() {
+              history.entries.push(entry);
+            }
+          },
+          Err(_) => break,
+        }
+      }
+    }
+
+    history.path = Some(path);
+
+    // Keep the file from growing forever by rewriting it when it gets too long
+    if history.entries.len() > MAX_ENTRIES {
+      let overflow = history.entries.len() - MAX_ENTRIES;
+      history.entries.drain(0..overflow);
+      history.rewrite();
+    }
+
+    return history;
+  }
+
+  /// Adds `entry` as the newest entry in the history and stops any ongoing
+  /// browsing. Empty entries and entries that are the same as the newest one
+  /// are ignored.
+  pub fn push(&mut self, entry: &str) {
+    self.reset();
+
+    let entry = entry.replace('\n', " ");
+    if entry.is_empty() || self.entries.last() == Some(&entry) {
+      return;
+    }
+
+    self.entries.push(entry.clone());
+    if self.entries.len() > MAX_ENTRIES {
+      self.entries.remove(0);
+    }
+
+    if let Some(ref path) = self.path {
+      let file = std::fs::OpenOptions::new().create(true).append(true).open(path);
+
+      if let Ok(mut file) = file {
+        let _ = writeln!(file, "{}", entry);
+      }
+    }
+  }
+
+  /// Moves one step back in the history and returns that entry. `current` is
+  /// what is being typed right now, which we restore once `next` moves past
+  /// the newest entry.
+  pub fn previous(&mut self, current: &str) -> Option<String> {
+    let position = match self.position {
+      Some(0) => 0,
+      Some(position) => position - 1,
+      None => {
+        if self.entries.is_empty() {
+          return None;
+        }
+
+        self.draft = String::from(current);
+        self.entries.len() - 1
+      },
+    };
+
+    self.position = Some(position);
+    return Some(self.entries[position].clone());
+  }
+
+  /// Moves one step forward in the history and returns that entry, or the
+  /// draft given to `previous` when moving past the newest entry.
+  pub fn next(&mut self) -> Option<String> {
+    return match self.position {
+      Some(position) if position + 1 < self.entries.len() => {
+        self.position = Some(position + 1);
+        Some(self.entries[position + 1].clone())
+      },
+      Some(_) => {
+        self.position = None;
+        Some(self.draft.clone())
+      },
+      None => None,
+    }
+  }
+
+  /// Stops browsing through the history.
+  pub fn reset(&mut self) {
+    self.position = None;
+    self.draft.clear();
+  }
+
+  /// Returns the index of the newest entry that contains `query`, looking at
+  /// the entries from `from` (inclusive) and older.
+  pub fn search_backwards(&self, query: &str, from: usize) -> Option<usize> {
+    if self.entries.is_empty() {
+      return None;
+    }
+
+    let from = std::cmp::min(from, self.entries.len() - 1);
+    return (0..(from + 1)).rev().find(|&index| self.entries[index].contains(query));
+  }
+
+  pub fn get(&self, index: usize) -> Option<String> {
+    return self.entries.get(index).cloned();
+  }
+
+  fn rewrite(&self) {
+    if let Some(ref path) = self.path {
+      if let Ok(mut file) = std::fs::File::create(path) {
+        for entry in self.entries.iter() {
+          let _ = writeln!(file, "{}", entry);
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn history_with(entries: Vec<&str>) -> History {
+    let mut history = History::new();
+    for entry in entries {
+      history.push(entry);
+    }
+
+    return history;
+  }
+
+  #[test]
+  fn it_recalls_entries_newest_first() {
+    let mut history = history_with(vec!["foo", "bar"]);
+
+    assert_eq!(history.previous(""), Some("bar".to_string()));
+    assert_eq!(history.previous(""), Some("foo".to_string()));
+    assert_eq!(history.previous(""), Some("foo".to_string()));
+  }
+
+  #[test]
+  fn it_restores_the_draft_when_moving_past_the_newest_entry() {
+    let mut history = history_with(vec!["foo", "bar"]);
+
+    history.previous("ba");
+    history.previous("ba");
+
+    assert_eq!(history.next(), Some("bar".to_string()));
+    assert_eq!(history.next(), Some("ba".to_string()));
+    assert_eq!(history.next(), None);
+  }
+
+  #[test]
+  fn it_returns_nothing_for_an_empty_history() {
+    let mut history = History::new();
+
+    assert_eq!(history.previous("foo"), None);
+  }
+
+  #[test]
+  fn it_ignores_empty_and_repeated_entries() {
+    let history = history_with(vec!["foo", "", "foo", "bar", "foo"]);
+
+    assert_eq!(history.get(2), Some("foo".to_string()));
+    assert_eq!(history.get(3), None);
+  }
+
+  #[test]
+  fn it_searches_backwards_from_the_given_index() {
+    let history = history_with(vec!["neubauten", "bauhaus", "einstürzende"]);
+
+    assert_eq!(history.search_backwards("bau", 2), Some(1));
+    assert_eq!(history.search_backwards("bau", 0), Some(0));
+    assert_eq!(history.search_backwards("joy", 2), None);
+  }
+}
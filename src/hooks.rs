@@ -0,0 +1,218 @@
+use configuration;
+use control;
+use rustc_serialize::json::{Json};
+use rustify;
+use std;
+use std::collections::BTreeMap;
+use std::io::{Write};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+  TrackChange,
+  Pause,
+  Resume,
+  QueueEmpty,
+}
+
+impl Event {
+  /// The name given to the command in `NEUBAUTEN_EVENT`.
+  fn name(&self) -> &'static str {
+    return match self {
+      &Event::TrackChange => "track_change",
+      &Event::Pause => "pause",
+      &Event::Resume => "resume",
+      &Event::QueueEmpty => "queue_empty",
+    }
+  }
+
+  fn command<'a>(&self, commands: &'a configuration::HookCommands) -> Option<&'a String> {
+    return match self {
+      &Event::TrackChange => commands.on_track_change.as_ref(),
+      &Event::Pause => commands.on_pause.as_ref(),
+      &Event::Resume => commands.on_resume.as_ref(),
+      &Event::QueueEmpty => commands.on_queue_empty.as_ref(),
+    }
+  }
+}
+
+/// Runs the configured commands when the playback changes, by comparing
+/// what's playing with what was playing the last time it was told.
+pub struct Hooks {
+  /// The play generation of the track that was playing (see `update`).
+  generation: Option<u64>,
+
+  playing: bool,
+}
+
+impl Hooks {
+  pub fn new() -> Hooks {
+    return Hooks { generation: None, playing: false };
+  }
+
+  /// Runs the commands for whatever has happened since the last time this
+  /// was called. The commands run in the background.
+  ///
+  /// The `generation` changes whenever a track is started, so that a track
+  /// played again counts as a new track.
+  pub fn update(&mut self,
+                commands: &configuration::HookCommands,
+                track: Option<&rustify::Track>,
+                generation: u64,
+                playing: bool) {
+    for event in self.events(track.map(|_| generation), playing) {
+      if let Some(command) = event.command(commands) {
+        run(command, commands.timeout, event, track.map(control::TrackInfo::new));
+      }
+    }
+  }
+
+  fn events(&mut self, generation: Option<u64>, playing: bool) -> Vec<Event> {
+    let mut events: Vec<Event> = Vec::new();
+
+    match (self.generation, generation) {
+      (Some(previous), Some(current)) if previous == current => {
+        if self.playing && !playing {
+          events.push(Event::Pause);
+        } else if !self.playing && playing {
+          events.push(Event::Resume);
+        }
+      },
+      (_, Some(_)) => events.push(Event::TrackChange),
+      (Some(_), None) => events.push(Event::QueueEmpty),
+      (None, None) => (),
+    }
+
+    self.generation = generation;
+    self.playing = playing;
+
+    return events;
+  }
+}
+
+/// Runs the command on a thread of its own, killing it if it's still running
+/// after `timeout`. The track is given in environment variables and as JSON
+/// on stdin, and anything the command prints is thrown away so that it
+/// doesn't end up in the interface.
+pub fn run(command: &str,
+           timeout: Duration,
+           event: Event,
+           track: Option<control::TrackInfo>) -> std::thread::JoinHandle<()> {
+  let mut command_builder = Command::new("sh");
+  command_builder.arg("-c").arg(command)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .env("NEUBAUTEN_EVENT", event.name());
+
+  if let Some(ref track) = track {
+    command_builder
+      .env("NEUBAUTEN_URI", &track.uri)
+      .env("NEUBAUTEN_TITLE", &track.title)
+      .env("NEUBAUTEN_ARTIST", track.artists.join(", "))
+      .env("NEUBAUTEN_ALBUM", &track.album)
+      .env("NEUBAUTEN_DURATION", track.duration.as_secs().to_string());
+  }
+
+  let input = event_json(event, track.as_ref());
+
+  return std::thread::spawn(move || {
+    let mut child = match command_builder.spawn() {
+      Ok(child) => child,
+      Err(_) => return,
+    };
+
+    // The command doesn't have to read it, so a closed pipe is fine
+    if let Some(mut stdin) = child.stdin.take() {
+      let _ = writeln!(stdin, "{}", input);
+    }
+
+    let started_at = Instant::now();
+
+    loop {
+      match child.try_wait() {
+        Ok(None) if started_at.elapsed() < timeout => std::thread::sleep(Duration::from_millis(50)),
+        Ok(None) => {
+          let _ = child.kill();
+          let _ = child.wait();
+          return;
+        },
+        _ => return,
+      }
+    }
+  });
+}
+
+fn event_json(event: Event, track: Option<&control::TrackInfo>) -> String {
+  let mut object: BTreeMap<String, Json> = BTreeMap::new();
+  object.insert("event".to_string(), Json::String(event.name().to_string()));
+
+  object.insert("track".to_string(), match track {
+    Some(track) => {
+      let mut fields: BTreeMap<String, Json> = BTreeMap::new();
+      fields.insert("uri".to_string(), Json::String(track.uri.clone()));
+      fields.insert("title".to_string(), Json::String(track.title.clone()));
+      fields.insert("artists".to_string(), Json::Array(track.artists.iter().map(|artist| Json::String(artist.clone())).collect()));
+      fields.insert("album".to_string(), Json::String(track.album.clone()));
+      fields.insert("duration".to_string(), Json::U64(track.duration.as_secs()));
+      Json::Object(fields)
+    },
+    None => Json::Null,
+  });
+
+  return Json::Object(object).to_string();
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use control;
+  use rustc_serialize::json::{Json};
+  use std;
+  use std::io::{Read};
+  use std::time::{Duration, Instant};
+  use test_fixtures;
+
+  #[test]
+  fn it_tells_what_happened_to_the_playback() {
+    let mut hooks = Hooks::new();
+
+    assert_eq!(hooks.events(None, false), vec![]);
+    assert_eq!(hooks.events(Some(1), true), vec![Event::TrackChange]);
+    assert_eq!(hooks.events(Some(1), true), vec![]);
+    assert_eq!(hooks.events(Some(1), false), vec![Event::Pause]);
+    assert_eq!(hooks.events(Some(1), true), vec![Event::Resume]);
+    assert_eq!(hooks.events(Some(2), true), vec![Event::TrackChange]);
+    assert_eq!(hooks.events(None, false), vec![Event::QueueEmpty]);
+  }
+
+  #[test]
+  fn it_gives_the_track_to_the_command() {
+    let path = test_fixtures::temporary_path("hook");
+    let command = format!("(echo \"$NEUBAUTEN_EVENT $NEUBAUTEN_ARTIST\"; cat) > {}", path.display());
+    let track = control::TrackInfo {
+      artists: vec!["Einstürzende Neubauten".to_string(), "Blixa Bargeld".to_string()],
+      ..test_fixtures::track("spotify:track:1", "Zebulon")
+    };
+
+    run(&command, Duration::from_secs(5), Event::TrackChange, Some(track)).join().unwrap();
+
+    let mut output = String::new();
+    std::fs::File::open(&path).unwrap().read_to_string(&mut output).unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    let mut lines = output.lines();
+    assert_eq!(lines.next(), Some("track_change Einstürzende Neubauten, Blixa Bargeld"));
+    assert_eq!(Json::from_str(lines.next().unwrap()).unwrap().find("event"), Some(&Json::String("track_change".to_string())));
+  }
+
+  #[test]
+  fn it_kills_commands_that_take_too_long() {
+    let started_at = Instant::now();
+
+    run("sleep 10", Duration::from_millis(100), Event::Pause, None).join().unwrap();
+
+    assert!(started_at.elapsed() < Duration::from_secs(5));
+  }
+}
@@ -0,0 +1,250 @@
+use std::cmp;
+
+use unicode_width::UnicodeWidthChar;
+
+/// A single line of text being edited, with a cursor and the readline notion
+/// of killing (cutting) text which can later be yanked (pasted) back.
+pub struct LineEditor {
+  buffer: Vec<char>,
+
+  /// The position (in characters) where the next character will be inserted.
+  /// Always between `0` and `buffer.len()` (inclusive).
+  cursor: usize,
+
+  /// The text removed by the last kill command.
+  kill_buffer: Vec<char>,
+}
+
+impl LineEditor {
+  pub fn new() -> Self {
+    return LineEditor {
+      buffer: Vec::new(),
+      cursor: 0,
+      kill_buffer: Vec::new(),
+    };
+  }
+
+  pub fn value(&self) -> String {
+    return self.buffer.iter().cloned().collect();
+  }
+
+  /// Replaces the whole line with `value` and moves the cursor to the end.
+  pub fn set_value(&mut self, value: &str) {
+    self.buffer = value.chars().collect();
+    self.cursor = self.buffer.len();
+  }
+
+  pub fn cursor_position(&self) -> usize {
+    return self.cursor;
+  }
+
+  /// Returns the number of terminal cells taken up by the text before the
+  /// cursor, which is where the cursor should be drawn.
+  pub fn cursor_column(&self) -> usize {
+    return self.buffer[..self.cursor].iter().map(|&c| display_width(c)).sum();
+  }
+
+  pub fn is_empty(&self) -> bool {
+    return self.buffer.is_empty();
+  }
+
+  pub fn clear(&mut self) {
+    self.buffer.clear();
+    self.cursor = 0;
+  }
+
+  pub fn insert_char(&mut self, c: char) {
+    self.buffer.insert(self.cursor, c);
+    self.cursor += 1;
+  }
+
+  /// Inserts all of `value` at the cursor, e.g. when pasting. Line breaks are
+  /// turned into spaces since we only ever edit a single line.
+  pub fn insert_str(&mut self, value: &str) {
+    for c in value.chars() {
+      match c {
+        '\n' | '\r' => self.insert_char(' '),
+        c => self.insert_char(c),
+      }
+    }
+  }
+
+  pub fn move_left(&mut self) {
+    if self.cursor > 0 {
+      self.cursor -= 1;
+    }
+  }
+
+  pub fn move_right(&mut self) {
+    if self.cursor < self.buffer.len() {
+      self.cursor += 1;
+    }
+  }
+
+  pub fn move_home(&mut self) {
+    self.cursor = 0;
+  }
+
+  pub fn move_end(&mut self) {
+    self.cursor = self.buffer.len();
+  }
+
+  /// Removes the character before the cursor.
+  pub fn backspace(&mut self) {
+    if self.cursor > 0 {
+      self.cursor -= 1;
+      self.buffer.remove(self.cursor);
+    }
+  }
+
+  /// Removes the character under the cursor.
+  pub fn delete(&mut self) {
+    if self.cursor < self.buffer.len() {
+      self.buffer.remove(self.cursor);
+    }
+  }
+
+  /// Kills the word before the cursor, including any whitespace between the
+  /// word and the cursor (`Ctrl-W`).
+  pub fn kill_word_backward(&mut self) {
+    let mut start = self.cursor;
+
+    while start > 0 && self.buffer[start - 1].is_whitespace() {
+      start -= 1;
+    }
+
+    while start > 0 && !self.buffer[start - 1].is_whitespace() {
+      start -= 1;
+    }
+
+    self.kill(start, self.cursor);
+  }
+
+  /// Kills everything before the cursor (`Ctrl-U`).
+  pub fn kill_to_start(&mut self) {
+    let end = self.cursor;
+    self.kill(0, end);
+  }
+
+  /// Kills everything from the cursor to the end of the line (`Ctrl-K`).
+  pub fn kill_to_end(&mut self) {
+    let (start, end) = (self.cursor, self.buffer.len());
+    self.kill(start, end);
+  }
+
+  /// Inserts the last killed text at the cursor (`Ctrl-Y`).
+  pub fn yank(&mut self) {
+    let killed: String = self.kill_buffer.iter().cloned().collect();
+    self.insert_str(&killed);
+  }
+
+  fn kill(&mut self, start: usize, end: usize) {
+    if start == end {
+      return;
+    }
+
+    self.kill_buffer = self.buffer.drain(start..end).collect();
+    self.cursor = start;
+  }
+}
+
+/// Returns the number of terminal cells `c` takes up: two for e.g. CJK
+/// characters and most emoji, otherwise one. Like termbox, control and
+/// combining characters are given a cell of their own.
+pub fn display_width(c: char) -> usize {
+  return cmp::max(c.width().unwrap_or(1), 1);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn editor_with(value: &str) -> LineEditor {
+    let mut editor = LineEditor::new();
+    editor.set_value(value);
+
+    return editor;
+  }
+
+  #[test]
+  fn it_inserts_characters_at_the_cursor() {
+    let mut editor = editor_with("neuten");
+
+    editor.move_left();
+    editor.move_left();
+    editor.move_left();
+    editor.insert_str("ba");
+
+    assert_eq!(editor.value(), "neubaten");
+    assert_eq!(editor.cursor_position(), 5);
+  }
+
+  #[test]
+  fn it_counts_wide_characters_twice_in_the_cursor_column() {
+    let mut editor = editor_with("東京 🎵 x");
+    assert_eq!(editor.cursor_column(), 9);
+
+    editor.move_left();
+    editor.move_left();
+    assert_eq!(editor.cursor_position(), 4);
+    assert_eq!(editor.cursor_column(), 7);
+  }
+
+  #[test]
+  fn it_keeps_the_cursor_within_the_line() {
+    let mut editor = editor_with("foo");
+
+    editor.move_right();
+    assert_eq!(editor.cursor_position(), 3);
+
+    editor.move_home();
+    editor.move_left();
+    assert_eq!(editor.cursor_position(), 0);
+  }
+
+  #[test]
+  fn it_removes_characters_around_the_cursor() {
+    let mut editor = editor_with("abcd");
+
+    editor.move_left();
+    editor.move_left();
+    editor.backspace();
+    editor.delete();
+
+    assert_eq!(editor.value(), "ad");
+    assert_eq!(editor.cursor_position(), 1);
+  }
+
+  #[test]
+  fn it_kills_the_word_before_the_cursor() {
+    let mut editor = editor_with("einstürzende neubauten  ");
+
+    editor.kill_word_backward();
+
+    assert_eq!(editor.value(), "einstürzende ");
+  }
+
+  #[test]
+  fn it_kills_to_the_start_of_the_line_and_yanks_it_back() {
+    let mut editor = editor_with("foo bar");
+
+    editor.move_left();
+    editor.move_left();
+    editor.move_left();
+    editor.kill_to_start();
+    assert_eq!(editor.value(), "bar");
+
+    editor.move_end();
+    editor.yank();
+    assert_eq!(editor.value(), "barfoo ");
+  }
+
+  #[test]
+  fn it_turns_line_breaks_into_spaces_when_inserting() {
+    let mut editor = LineEditor::new();
+
+    editor.insert_str("foo\nbar");
+
+    assert_eq!(editor.value(), "foo bar");
+  }
+}
@@ -1,7 +1,10 @@
 extern crate rustbox;
 extern crate core;
 
+use configuration;
 use std;
+use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::fmt::{Display};
 use rustbox::{Color};
 
@@ -21,6 +24,28 @@ pub struct List<T: Display + Clone> {
   print_from_index: usize,
 
   current_filter: Option<String>,
+
+  /// The `cursor_index` where the visual selection was started, if we're
+  /// selecting a range of items (like vim's `V`).
+  visual_start: Option<usize>,
+
+  /// Indexes of the `items` that have been marked. Marks are kept when the
+  /// filter changes so that items can be marked across several filters.
+  marked: HashSet<usize>,
+
+  /// Indexes of the `items` in the order they're shown, without the ones
+  /// that don't meet the `condition`.
+  shown: Vec<usize>,
+
+  /// How the items are sorted, if they aren't shown in their own order.
+  compare: Option<Box<Fn(&T, &T) -> Ordering>>,
+
+  /// Which items to show, apart from the `current_filter`. Unlike the filter
+  /// it's only checked when the items or the condition changes.
+  condition: Option<Box<Fn(&T) -> bool>>,
+
+  /// Returns text shown in front of every item (e.g. play counts).
+  details: Option<Box<Fn(&T) -> String>>,
 }
 
 impl<T: Display + Clone> List<T> {
@@ -32,8 +57,15 @@ impl<T: Display + Clone> List<T> {
       matching_indexes: Vec::new(),
       print_from_index: 0,
       current_filter: None,
+      visual_start: None,
+      marked: HashSet::new(),
+      shown: Vec::new(),
+      compare: None,
+      condition: None,
+      details: None,
     };
 
+    list.arrange();
     list.update_matches();
 
     return list;
@@ -42,14 +74,45 @@ impl<T: Display + Clone> List<T> {
   fn update_matches(&mut self) {
     self.matching_indexes.clear();
 
-    for (index, item) in self.items.iter().enumerate() {
-      if self.does_item_match_filter(&item, &self.current_filter) {
+    for &index in self.shown.iter() {
+      if self.does_item_match_filter(&self.items[index], &self.current_filter) {
         self.matching_indexes.push(index);
       }
     }
   }
 
-  pub fn print(&mut self, rustbox: &rustbox::RustBox, x_pos: usize, y_pos: usize, reset_cursor: bool) {
+  /// Works out which items are shown and in what order, from the `condition`
+  /// and `compare`.
+  fn arrange(&mut self) {
+    let mut shown: Vec<usize> = (0..self.items.len()).collect();
+
+    if let Some(ref condition) = self.condition {
+      let items = &self.items;
+      shown.retain(|&index| condition(&items[index]));
+    }
+
+    if let Some(ref compare) = self.compare {
+      let items = &self.items;
+      shown.sort_by(|&a, &b| compare(&items[a], &items[b]));
+    }
+
+    self.shown = shown;
+  }
+
+  /// Moves the cursor back to the first item, after the items shown have
+  /// changed.
+  fn reset_cursor(&mut self) {
+    self.cursor_index = 0;
+    self.print_from_index = 0;
+    self.visual_start = None;
+  }
+
+  pub fn print(&mut self,
+               rustbox: &rustbox::RustBox,
+               theme: &configuration::Theme,
+               x_pos: usize,
+               y_pos: usize,
+               reset_cursor: bool) {
     if self.items.is_empty() {
       return;
     }
@@ -73,10 +136,16 @@ impl<T: Display + Clone> List<T> {
     let items_to_display = &items[(self.print_from_index)..(max_index + self.print_from_index)];
 
     for item in items_to_display {
-      let value: String = format!(" {} ", item);
+      let is_marked = self.marked.contains(&self.matching_indexes[index + self.print_from_index]);
+      let details = self.details.as_ref().map_or(String::new(), |details| details(item));
+      let value: String = format!("{}{}{} ", if is_marked { "*" } else { " " }, details, item);
 
       if (index + self.print_from_index) == self.cursor_index {
-        rustbox.print(x_pos, y_pos + index, rustbox::RB_BOLD, Color::White, Color::Black, &value);
+        rustbox.print(x_pos, y_pos + index, rustbox::RB_BOLD, theme.cursor.foreground, theme.cursor.background, &value);
+      } else if self.is_in_visual_selection(index + self.print_from_index) {
+        rustbox.print(x_pos, y_pos + index, rustbox::RB_REVERSE, Color::Default, Color::Default, &value);
+      } else if is_marked {
+        rustbox.print(x_pos, y_pos + index, rustbox::RB_BOLD, theme.marked.foreground, theme.marked.background, &value);
       } else {
         rustbox.print(x_pos, y_pos + index, rustbox::RB_NORMAL, Color::Default, Color::Default, &value);
       }
@@ -85,18 +154,203 @@ impl<T: Display + Clone> List<T> {
     }
   }
 
+  /// Replaces the items in the list (e.g. after the underlying playlist has
+  /// been changed) while keeping the cursor where it was, as far as possible.
+  pub fn set_items(&mut self, items: Vec<T>) {
+    self.items = items;
+    self.visual_start = None;
+    self.marked.clear();
+    self.arrange();
+    self.update_matches();
+
+    if self.cursor_index >= self.matching_indexes.len() {
+      self.cursor_index = if self.matching_indexes.is_empty() { 0 } else { self.matching_indexes.len() - 1 };
+    }
+
+    // Make sure that we don't scroll past the last item
+    let last_screen_starts_at = self.matching_indexes.len().saturating_sub(self.height);
+    self.print_from_index = std::cmp::min(self.print_from_index, last_screen_starts_at);
+
+    if self.cursor_index < self.print_from_index {
+      self.print_from_index = self.cursor_index;
+    }
+  }
+
+  /// Starts selecting a range of items from the cursor, or stops selecting if
+  /// we already are.
+  pub fn toggle_visual(&mut self) {
+    self.visual_start = match self.visual_start {
+      Some(_) => None,
+      None => Some(self.cursor_index),
+    };
+  }
+
+  /// Stops selecting a range of items and removes all marks. Returns whether
+  /// or not there was anything selected.
+  pub fn clear_selection(&mut self) -> bool {
+    let had_selection = self.visual_start.is_some() || !self.marked.is_empty();
+
+    self.visual_start = None;
+    self.marked.clear();
+
+    return had_selection;
+  }
+
+  /// Marks (or unmarks) the item under the cursor. When selecting a range all
+  /// of the items in it are marked instead, which also ends the range.
+  pub fn toggle_mark(&mut self) {
+    if self.matching_indexes.is_empty() {
+      return;
+    }
+
+    if self.visual_start.is_some() {
+      let (start, end) = self.visual_range();
+      for cursor_index in start..(end + 1) {
+        self.marked.insert(self.matching_indexes[cursor_index]);
+      }
+
+      self.visual_start = None;
+    } else {
+      let index = self.get_selected_index();
+      if !self.marked.remove(&index) {
+        self.marked.insert(index);
+      }
+    }
+  }
+
+  /// Marks all of the items matching the current filter.
+  pub fn mark_all_matching(&mut self) {
+    for &index in self.matching_indexes.iter() {
+      self.marked.insert(index);
+    }
+  }
+
+  /// Marks the items matching the current filter that aren't marked and
+  /// unmarks the ones that are.
+  pub fn invert_marks(&mut self) {
+    for &index in self.matching_indexes.iter() {
+      if !self.marked.remove(&index) {
+        self.marked.insert(index);
+      }
+    }
+  }
+
+  /// Returns all of the marked items in the order they appear in the list,
+  /// including the ones hidden by the current filter.
+  pub fn get_marked_items(&self) -> Vec<T> {
+    return self.items.iter().enumerate().filter(|&(index, _)| {
+      self.marked.contains(&index)
+    }).map(|(_, item)| item.clone()).collect();
+  }
+
+  /// Returns the items to run an action on: the items in the visual selection
+  /// if we're selecting a range, otherwise the marked items, or the item under
+  /// the cursor if nothing has been marked.
+  pub fn get_selected_items(&self) -> Vec<T> {
+    if self.visual_start.is_none() && !self.marked.is_empty() {
+      return self.get_marked_items();
+    }
+
+    // Marked items are selected even when the filter hides them, but there's
+    // no item under the cursor when nothing matches
+    if self.matching_indexes.is_empty() {
+      return Vec::new();
+    }
+
+    let (start, end) = self.visual_range();
+    return self.matching_indexes[start..(end + 1)].iter().map(|&index| {
+      self.items[index].clone()
+    }).collect();
+  }
+
+  /// Returns the first and last `cursor_index` (inclusive) of the visual
+  /// selection, which is just the cursor if we're not selecting a range.
+  fn visual_range(&self) -> (usize, usize) {
+    let start = self.visual_start.unwrap_or(self.cursor_index);
+    return (std::cmp::min(start, self.cursor_index), std::cmp::max(start, self.cursor_index));
+  }
+
+  fn is_in_visual_selection(&self, cursor_index: usize) -> bool {
+    let (start, end) = self.visual_range();
+    return self.visual_start.is_some() && start <= cursor_index && cursor_index <= end;
+  }
+
+  /// Moves the cursor to the item at `index` in `items`, if it matches the
+  /// current filter.
+  pub fn select_index(&mut self, index: usize) {
+    match self.matching_indexes.iter().position(|&matching_index| matching_index == index) {
+      Some(cursor_index) => {
+        while self.cursor_index < cursor_index {
+          self.handle_down();
+        }
+
+        while self.cursor_index > cursor_index {
+          self.handle_up();
+        }
+      },
+      None => (),
+    }
+  }
+
   pub fn set_filter(&mut self, value: &str) {
     self.cursor_index = 0;
+    self.visual_start = None;
     self.current_filter = Some(String::from(value));
     self.update_matches();
   }
 
+  /// Removes the filter as well as the condition.
   pub fn clear_filter(&mut self) {
     self.cursor_index = 0;
+    self.visual_start = None;
     self.current_filter = None;
+
+    if self.condition.is_some() {
+      self.condition = None;
+      self.arrange();
+      self.print_from_index = 0;
+    }
+
+    self.update_matches();
+  }
+
+  /// Shows the items in the order given by `compare` (keeping the order of
+  /// items that compare equal), until `clear_sort` is called. The order is
+  /// kept when the items are replaced.
+  pub fn sort_by(&mut self, compare: Box<Fn(&T, &T) -> Ordering>) {
+    self.compare = Some(compare);
+    self.arrange();
+    self.reset_cursor();
+    self.update_matches();
+  }
+
+  /// Shows the items in their own order again.
+  pub fn clear_sort(&mut self) {
+    self.compare = None;
+    self.arrange();
+    self.reset_cursor();
     self.update_matches();
   }
 
+  /// Only shows the items meeting the `condition`, until the filter is
+  /// cleared.
+  pub fn set_condition(&mut self, condition: Box<Fn(&T) -> bool>) {
+    self.condition = Some(condition);
+    self.arrange();
+    self.reset_cursor();
+    self.update_matches();
+  }
+
+  /// Returns whether the items are shown in another order than their own, or
+  /// some of them are left out, because of `sort_by` or `set_condition`.
+  pub fn is_rearranged(&self) -> bool {
+    return self.compare.is_some() || self.condition.is_some();
+  }
+
+  pub fn set_details(&mut self, details: Option<Box<Fn(&T) -> String>>) {
+    self.details = details;
+  }
+
   pub fn handle_down(&mut self) {
     if !self.is_cursor_at_last_item() {
       self.cursor_index += 1;
@@ -137,6 +391,18 @@ impl<T: Display + Clone> List<T> {
     return self.items[self.get_selected_index()].clone();
   }
 
+  /// Returns whether no items match the current filter, in which case there's
+  /// no selected item either.
+  pub fn is_empty(&self) -> bool {
+    return self.matching_indexes.is_empty();
+  }
+
+  /// Returns all of the items matching the current filter (or all of them if
+  /// there's no filter).
+  pub fn get_matching_items(&self) -> Vec<T> {
+    return self.matching_indexes.iter().map(|&index| self.items[index].clone()).collect();
+  }
+
   fn is_cursor_out_of_screen(&self) -> bool {
     let screen_starts_at = self.print_from_index;
     let screen_ends_at = screen_starts_at + self.height - 1;
@@ -145,7 +411,8 @@ impl<T: Display + Clone> List<T> {
   }
 
   fn is_cursor_at_last_item(&self) -> bool {
-    return self.cursor_index == (self.matching_indexes.len() - 1);
+    // Nothing matching counts too, so that the cursor can't move at all
+    return self.cursor_index + 1 >= self.matching_indexes.len();
   }
 
   fn does_item_match_filter(&self, item: &T, filter: &Option<String>) -> bool {
@@ -262,4 +529,221 @@ mod tests {
 
     assert_eq!(item, "foo");
   }
+
+  #[test]
+  fn it_keeps_the_cursor_within_the_list_when_replacing_the_items() {
+    let items = vec![ "foo", "bar", "baz" ];
+    let mut list = List::new(items, 10);
+
+    list.handle_bottom();
+    list.set_items(vec![ "foo", "bar" ]);
+
+    assert_eq!(list.get_selected_index(), 1);
+  }
+
+  #[test]
+  fn it_can_move_the_cursor_to_an_item() {
+    let items = vec![ "foo", "bar", "baz" ];
+    let mut list = List::new(items, 1);
+
+    list.select_index(2);
+    assert_eq!(list.get_selected_item(), "baz");
+
+    list.select_index(0);
+    assert_eq!(list.get_selected_item(), "foo");
+  }
+
+  #[test]
+  fn it_returns_the_items_matching_the_filter() {
+    let items = vec![ "foo", "bar", "baz" ];
+    let mut list = List::new(items, 10);
+
+    list.set_filter("ba");
+
+    assert_eq!(list.get_matching_items(), vec![ "bar", "baz" ]);
+  }
+
+  #[test]
+  fn it_returns_the_item_under_the_cursor_as_selected_without_a_visual_selection() {
+    let items = vec![ "foo", "bar", "baz" ];
+    let mut list = List::new(items, 10);
+
+    list.handle_down();
+
+    assert_eq!(list.get_selected_items(), vec![ "bar" ]);
+  }
+
+  #[test]
+  fn it_selects_a_range_of_items_in_visual_mode() {
+    let items = vec![ "foo", "bar", "baz", "qux" ];
+    let mut list = List::new(items, 10);
+
+    list.handle_bottom();
+    list.toggle_visual();
+    list.handle_up();
+    list.handle_up();
+
+    assert_eq!(list.get_selected_items(), vec![ "bar", "baz", "qux" ]);
+  }
+
+  #[test]
+  fn it_stops_the_visual_selection_when_filtering() {
+    let items = vec![ "foo", "bar", "baz" ];
+    let mut list = List::new(items, 10);
+
+    list.toggle_visual();
+    list.set_filter("ba");
+
+    assert_eq!(list.clear_selection(), false);
+  }
+
+  #[test]
+  fn it_does_not_scroll_past_the_last_item_when_replacing_the_items() {
+    let items = vec![ "foo", "bar", "baz" ];
+    let mut list = List::new(items, 2);
+
+    list.handle_bottom();
+    list.set_items(vec![ "foo", "bar" ]);
+
+    assert_eq!(list.print_from_index, 0);
+    assert_eq!(list.get_selected_index(), 1);
+  }
+
+  #[test]
+  fn it_is_empty_after_the_last_item_is_removed() {
+    let mut list = List::new(vec![ "foo" ], 2);
+    assert!(!list.is_empty());
+
+    list.set_items(Vec::new());
+    list.handle_down();
+    list.handle_up();
+
+    assert!(list.is_empty());
+    assert!(list.get_selected_items().is_empty());
+  }
+
+  #[test]
+  fn it_can_mark_and_unmark_items() {
+    let items = vec![ "foo", "bar", "baz" ];
+    let mut list = List::new(items, 10);
+
+    list.toggle_mark();
+    list.handle_bottom();
+    list.toggle_mark();
+    list.handle_top();
+    list.toggle_mark();
+
+    assert_eq!(list.get_marked_items(), vec![ "baz" ]);
+  }
+
+  #[test]
+  fn it_marks_the_visual_selection() {
+    let items = vec![ "foo", "bar", "baz" ];
+    let mut list = List::new(items, 10);
+
+    list.toggle_visual();
+    list.handle_down();
+    list.toggle_mark();
+    list.handle_bottom();
+
+    assert_eq!(list.get_selected_items(), vec![ "foo", "bar" ]);
+  }
+
+  #[test]
+  fn it_selects_the_marked_items_when_nothing_matches_the_filter() {
+    let items = vec![ "foo", "bar", "baz" ];
+    let mut list = List::new(items, 10);
+
+    list.handle_down();
+    list.toggle_mark();
+    list.set_filter("qux");
+
+    assert_eq!(list.get_selected_items(), vec![ "bar" ]);
+  }
+
+  #[test]
+  fn it_marks_all_items_matching_the_filter() {
+    let items = vec![ "foo", "bar", "baz" ];
+    let mut list = List::new(items, 10);
+
+    list.set_filter("ba");
+    list.mark_all_matching();
+    list.clear_filter();
+
+    assert_eq!(list.get_marked_items(), vec![ "bar", "baz" ]);
+  }
+
+  #[test]
+  fn it_inverts_the_marks_of_items_matching_the_filter() {
+    let items = vec![ "foo", "bar", "baz" ];
+    let mut list = List::new(items, 10);
+
+    list.toggle_mark();
+    list.handle_down();
+    list.toggle_mark();
+    list.set_filter("ba");
+    list.invert_marks();
+
+    assert_eq!(list.get_marked_items(), vec![ "foo", "baz" ]);
+  }
+
+  #[test]
+  fn it_clears_both_the_marks_and_the_visual_selection() {
+    let items = vec![ "foo", "bar" ];
+    let mut list = List::new(items, 10);
+
+    list.toggle_mark();
+    list.toggle_visual();
+
+    assert_eq!(list.clear_selection(), true);
+    assert_eq!(list.get_marked_items(), Vec::<&str>::new());
+    assert_eq!(list.clear_selection(), false);
+  }
+
+  #[test]
+  fn it_shows_the_items_in_the_sorted_order() {
+    let items = vec![ "foo", "bar", "baz" ];
+    let mut list = List::new(items, 10);
+
+    list.sort_by(Box::new(|a: &&str, b: &&str| a.cmp(b)));
+    list.handle_down();
+
+    // The index is still the one in `items`
+    assert_eq!(list.get_selected_index(), 2);
+    assert_eq!(list.get_matching_items(), vec![ "bar", "baz", "foo" ]);
+
+    list.set_items(vec![ "qux", "foo" ]);
+    assert_eq!(list.get_matching_items(), vec![ "foo", "qux" ]);
+
+    list.clear_sort();
+    assert_eq!(list.get_matching_items(), vec![ "qux", "foo" ]);
+  }
+
+  #[test]
+  fn it_only_shows_the_items_meeting_the_condition_until_the_filter_is_cleared() {
+    let items = vec![ "foo", "bar", "baz" ];
+    let mut list = List::new(items, 10);
+
+    list.set_condition(Box::new(|item: &&str| item.starts_with("b")));
+    list.set_filter("z");
+    assert_eq!(list.get_matching_items(), vec![ "baz" ]);
+
+    assert!(list.is_rearranged());
+
+    list.clear_filter();
+    assert_eq!(list.get_matching_items(), vec![ "foo", "bar", "baz" ]);
+    assert!(!list.is_rearranged());
+  }
+
+  #[test]
+  fn it_does_not_move_the_cursor_when_nothing_matches() {
+    let items = vec![ "foo", "bar" ];
+    let mut list = List::new(items, 10);
+
+    list.set_filter("qux");
+    list.handle_down();
+    list.clear_filter();
+
+    assert_eq!(list.get_selected_index(), 0);
+  }
 }
@@ -0,0 +1,314 @@
+use libc;
+use line_editor::{LineEditor};
+use rustbox;
+use rustify;
+use std;
+use std::io::{BufRead, Write};
+
+use rustbox::{Color};
+
+/// Characters cycled through to show that we're still waiting.
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+enum Screen {
+  /// Asking for the username.
+  Username(LineEditor),
+
+  /// Asking for the password of the given username.
+  Password(String, LineEditor),
+
+  /// Waiting to be signed in as the given username since the given instant.
+  LoggingIn(String, std::time::Instant),
+
+  /// Signing in as the given username failed with the given error.
+  Failed(String, String),
+}
+
+/// Signs in to Spotify while showing the progress (and any errors) in the
+/// terminal. Any credentials missing are asked for, and the user can retry
+/// (or change the credentials) when signing in fails.
+///
+/// Without a password we sign in with the credentials libspotify remembered
+/// from the last time, if it remembers the user we're signing in as.
+///
+/// Returns the username we're signed in as, or `None` if the user chose to
+/// quit instead.
+pub fn login(session: &mut rustify::Session,
+             receiver: &std::sync::mpsc::Receiver<rustify::Event>,
+             rustbox: &rustbox::RustBox,
+             username: Option<String>,
+             password: Option<String>,
+             warnings: &Vec<String>) -> Option<String> {
+  let mut password: Option<String> = password;
+  let remembered_user = session.remembered_user();
+
+  let mut screen = match (username, password.clone(), remembered_user) {
+    (Some(username), Some(password), _) => {
+      session.login_and_remember(&username, &password);
+      Screen::LoggingIn(username, std::time::Instant::now())
+    },
+    (None, None, Some(remembered_user)) => relogin(session, remembered_user),
+    (Some(username), None, Some(remembered_user)) => {
+      if username == remembered_user {
+        relogin(session, username)
+      } else {
+        Screen::Password(username, LineEditor::new())
+      }
+    },
+    (Some(username), None, None) => Screen::Password(username, LineEditor::new()),
+    (None, _, _) => Screen::Username(LineEditor::new()),
+  };
+
+  loop {
+    print_login_screen(rustbox, &screen, warnings);
+
+    let mut login_error: Option<String> = None;
+
+    if let Screen::LoggingIn(ref username, _) = screen {
+      match receiver.try_recv() {
+        Ok(rustify::Event::LoggedIn) => return Some(username.clone()),
+        Ok(rustify::Event::LoginFailed(e)) => login_error = Some(error_message(&e)),
+        Ok(rustify::Event::ConnectionError(e)) => login_error = Some(error_message(&e)),
+        _ => (),
+      }
+    }
+
+    if let Some(error) = login_error {
+      screen = match screen {
+        Screen::LoggingIn(username, _) => Screen::Failed(username, error),
+        screen => screen,
+      };
+
+      continue;
+    }
+
+    let key = match rustbox.peek_event(std::time::Duration::from_millis(100), false) {
+      Ok(rustbox::Event::KeyEvent(key)) => key,
+      _ => continue,
+    };
+
+    if key == rustbox::Key::Esc {
+      return None;
+    }
+
+    screen = match screen {
+      Screen::Username(mut editor) => {
+        match key {
+          rustbox::Key::Enter if !editor.is_empty() => {
+            match password {
+              Some(ref password) => {
+                session.login_and_remember(&editor.value(), password);
+                Screen::LoggingIn(editor.value(), std::time::Instant::now())
+              },
+              None => Screen::Password(editor.value(), LineEditor::new()),
+            }
+          },
+          key => {
+            edit(&mut editor, key);
+            Screen::Username(editor)
+          },
+        }
+      },
+      Screen::Password(username, mut editor) => {
+        match key {
+          rustbox::Key::Enter if !editor.is_empty() => {
+            password = Some(editor.value());
+            session.login_and_remember(&username, &editor.value());
+            Screen::LoggingIn(username, std::time::Instant::now())
+          },
+          key => {
+            edit(&mut editor, key);
+            Screen::Password(username, editor)
+          },
+        }
+      },
+      Screen::Failed(username, error) => {
+        match (key, password.clone()) {
+          (rustbox::Key::Char('q'), _) => return None,
+          (rustbox::Key::Char('r'), Some(ref password)) | (rustbox::Key::Enter, Some(ref password)) => {
+            session.login_and_remember(&username, password);
+            Screen::LoggingIn(username, std::time::Instant::now())
+          },
+          (rustbox::Key::Char('r'), None) | (rustbox::Key::Enter, None) => {
+            relogin(session, username)
+          },
+          (rustbox::Key::Char('c'), _) => {
+            // Start over, asking for both the username and the password
+            password = None;
+
+            let mut editor = LineEditor::new();
+            editor.set_value(&username);
+            Screen::Username(editor)
+          },
+          _ => Screen::Failed(username, error),
+        }
+      },
+      Screen::LoggingIn(username, started_at) => {
+        match key {
+          rustbox::Key::Char('q') => return None,
+          _ => Screen::LoggingIn(username, started_at),
+        }
+      },
+    };
+  }
+}
+
+/// Signs in without the interface (e.g. for the commands given on the
+/// command line), asking for any missing credentials on the terminal.
+///
+/// Returns the username we're signed in as, or why signing in failed.
+pub fn login_on_terminal(session: &mut rustify::Session,
+                         receiver: &std::sync::mpsc::Receiver<rustify::Event>,
+                         username: Option<String>,
+                         password: Option<String>) -> Result<String, String> {
+  let remembered_user = session.remembered_user();
+
+  let username = match (username, &password, &remembered_user) {
+    (Some(username), _, _) => username,
+    (None, &None, &Some(ref remembered_user)) => remembered_user.clone(),
+    (None, _, _) => try!(prompt("Username: ", false)),
+  };
+
+  match password {
+    Some(password) => session.login_and_remember(&username, &password),
+    None => {
+      if remembered_user.as_ref() != Some(&username) || !session.relogin() {
+        let password = try!(prompt("Password: ", true));
+        session.login_and_remember(&username, &password);
+      }
+    },
+  }
+
+  for event in receiver.iter() {
+    match event {
+      rustify::Event::LoggedIn => return Ok(username),
+      rustify::Event::LoginFailed(e) => return Err(error_message(&e)),
+      rustify::Event::ConnectionError(e) => return Err(error_message(&e)),
+      _ => (),
+    }
+  }
+
+  return Err("The session stopped before signing in".to_string());
+}
+
+/// Asks for a line on the terminal, without showing what's typed if it's
+/// `hidden`.
+fn prompt(question: &str, hidden: bool) -> Result<String, String> {
+  let mut stderr = std::io::stderr();
+  let _ = write!(stderr, "{}", question);
+  let _ = stderr.flush();
+
+  // Turn off the echo while typing, like `read -s` does
+  let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+  let echo_off = hidden && unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } == 0;
+
+  if echo_off {
+    let mut without_echo = termios;
+    without_echo.c_lflag &= !libc::ECHO;
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &without_echo) };
+  }
+
+  let mut line = String::new();
+  let result = std::io::stdin().lock().read_line(&mut line);
+
+  if echo_off {
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) };
+    let _ = writeln!(stderr, "");
+  }
+
+  return match result {
+    Ok(0) => Err("Unable to sign in without the credentials".to_string()),
+    Ok(_) => Ok(line.trim_right_matches(|c| c == '\n' || c == '\r').to_string()),
+    Err(e) => Err(format!("Unable to read the credentials: {}", e)),
+  }
+}
+
+/// Signs in with the remembered credentials, or asks for the password if
+/// there aren't any (e.g. if they've been forgotten).
+fn relogin(session: &mut rustify::Session, username: String) -> Screen {
+  if session.relogin() {
+    return Screen::LoggingIn(username, std::time::Instant::now());
+  } else {
+    return Screen::Password(username, LineEditor::new());
+  }
+}
+
+fn edit(editor: &mut LineEditor, key: rustbox::Key) {
+  match key {
+    rustbox::Key::Char(c) => editor.insert_char(c),
+    rustbox::Key::Backspace => editor.backspace(),
+    rustbox::Key::Delete => editor.delete(),
+    rustbox::Key::Left => editor.move_left(),
+    rustbox::Key::Right => editor.move_right(),
+    rustbox::Key::Home | rustbox::Key::Ctrl('a') => editor.move_home(),
+    rustbox::Key::End | rustbox::Key::Ctrl('e') => editor.move_end(),
+    rustbox::Key::Ctrl('u') => editor.kill_to_start(),
+    rustbox::Key::Ctrl('w') => editor.kill_word_backward(),
+    _ => (),
+  }
+}
+
+/// Turns the errors we can get when signing in into something that tells the
+/// user what to do about it.
+fn error_message(error: &rustify::Error) -> String {
+  return match error {
+    &rustify::Error::BadUsernameOrPassword => {
+      "Wrong username or password".to_string()
+    },
+    &rustify::Error::UnableToContactServer => {
+      "Unable to contact Spotify, are you offline?".to_string()
+    },
+    &rustify::Error::BadApplicationKey => {
+      "The application key is not valid, see the README on how to get one".to_string()
+    },
+    &rustify::Error::UserNeedsPremium => {
+      "A Spotify Premium account is needed to use neubauten".to_string()
+    },
+    error => format!("Unable to sign in: {}", error),
+  }
+}
+
+fn print_login_screen(rustbox: &rustbox::RustBox, screen: &Screen, warnings: &Vec<String>) {
+  let y_pos = rustbox.height() / 2;
+
+  rustbox.clear();
+  rustbox.set_cursor(-1, -1);
+  rustbox.print(2, y_pos.saturating_sub(2), rustbox::RB_BOLD, Color::Default, Color::Default, "neubauten");
+
+  match screen {
+    &Screen::Username(ref editor) => {
+      print_prompt(rustbox, y_pos, "Username: ", &editor.value(), editor.cursor_position());
+    },
+    &Screen::Password(ref username, ref editor) => {
+      let masked: String = editor.value().chars().map(|_| '*').collect();
+
+      rustbox.print(2, y_pos.saturating_sub(1), rustbox::RB_NORMAL, Color::Default, Color::Default,
+                    &format!("Signing in as {}", username));
+      print_prompt(rustbox, y_pos, "Password: ", &masked, editor.cursor_position());
+    },
+    &Screen::LoggingIn(ref username, ref started_at) => {
+      let elapsed = started_at.elapsed();
+      let tick = (elapsed.as_secs() * 10 + (elapsed.subsec_nanos() / 100_000_000) as u64) as usize;
+
+      let value = format!("Signing in as {}... {}", username, SPINNER[tick % SPINNER.len()]);
+      rustbox.print(2, y_pos, rustbox::RB_NORMAL, Color::Default, Color::Default, &value);
+    },
+    &Screen::Failed(_, ref error) => {
+      rustbox.print(2, y_pos, rustbox::RB_BOLD, Color::Red, Color::Default, error);
+      rustbox.print(2, y_pos + 1, rustbox::RB_NORMAL, Color::Default, Color::Default,
+                    "Press r to try again, c to change username and password or q to quit");
+    },
+  }
+
+  for (index, warning) in warnings.iter().enumerate() {
+    rustbox.print(2, y_pos + 3 + index, rustbox::RB_NORMAL, Color::Yellow, Color::Default,
+                  &format!("Warning: {}", warning));
+  }
+
+  rustbox.present();
+}
+
+fn print_prompt(rustbox: &rustbox::RustBox, y_pos: usize, prompt: &str, value: &str, cursor: usize) {
+  rustbox.print(2, y_pos, rustbox::RB_NORMAL, Color::Default, Color::Default, &format!("{}{}", prompt, value));
+  rustbox.set_cursor((2 + prompt.len() + cursor) as isize, y_pos as isize);
+}
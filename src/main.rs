@@ -4,23 +4,48 @@ extern crate rustbox;
 extern crate rustify;
 extern crate libc;
 extern crate rustc_serialize;
+extern crate toml;
+extern crate curl;
+extern crate md5;
+extern crate unicode_width;
+#[cfg(any(feature = "mpris", feature = "notifications"))]
+extern crate dbus;
 
 extern {
   static g_appkey: *const u8;
   static g_appkey_size: libc::size_t;
 }
 
+mod cli;
 mod configuration;
+mod control;
+mod mpd;
+mod mpris;
 mod list;
 mod action;
 mod view;
 mod command_parser;
+mod history;
+mod hooks;
+mod line_editor;
+mod login;
+mod notifications;
+mod playlist_tree;
+mod plays;
+mod scrobble;
+mod status_file;
+
+/// Fixtures shared by the tests of the modules.
+#[cfg(test)]
+mod test_fixtures;
 
 use view::{NeubautenView};
 use action::{Action};
 use command_parser::{CommandParser};
+use history::{History};
 
 use std::default::Default;
+use std::io::Write;
 use std::sync::{Arc, Mutex};
 
 use rustbox::{RustBox};
@@ -40,6 +65,35 @@ enum PlaybackTrack {
 
   /// Contains the search, track and the `search_index`
   SearchTrack(rustify::Track),
+
+  /// Contains the tracks of the album and the index of the playing track
+  AlbumTrack(Vec<rustify::Track>, usize),
+
+  ArtistTrack(rustify::Track),
+
+  /// A track picked from the most played tracks
+  StatsTrack(rustify::Track),
+}
+
+impl PlaybackTrack {
+  fn track(&self) -> &rustify::Track {
+    return match self {
+      &PlaybackTrack::QueueTrack(ref track) => track,
+      &PlaybackTrack::PlaylistTrack(_, ref track, _) => track,
+      &PlaybackTrack::SearchTrack(ref track) => track,
+      &PlaybackTrack::AlbumTrack(ref tracks, track_index) => &tracks[track_index],
+      &PlaybackTrack::ArtistTrack(ref track) => track,
+      &PlaybackTrack::StatsTrack(ref track) => track,
+    }
+  }
+
+  /// The name of the playlist the track is played from, if any.
+  fn playlist(&self) -> Option<String> {
+    return match self {
+      &PlaybackTrack::PlaylistTrack(ref playlist, _, _) => Some(playlist.to_string()),
+      _ => None,
+    }
+  }
 }
 
 fn result_to_option<T, E>(event: Result<T, E>) -> Option<T> {
@@ -71,31 +125,243 @@ fn get_next_action(receiver: &std::sync::mpsc::Receiver<rustify::Event>,
   return action;
 }
 
+/// Returns the track on the selected row of the `view`, if the row is a track.
+fn get_selected_track(view: &NeubautenView) -> Option<rustify::Track> {
+  return match view {
+    &NeubautenView::TrackView(_, ref list) => Some(list.get_selected_item()),
+    &NeubautenView::SearchView(_, ref list) => Some(list.get_selected_item()),
+    &NeubautenView::AlbumView(_, ref list) => Some(list.get_selected_item().track),
+    &NeubautenView::ArtistView(_, ref list) => {
+      match list.get_selected_item() {
+        view::ArtistItem::TopTrack(track) => Some(track),
+        _ => None,
+      }
+    },
+    &NeubautenView::StatsView(_, ref list) => {
+      match list.get_selected_item() {
+        view::StatsItem::Track(_, _, track) => track,
+        view::StatsItem::Artist(..) => None,
+      }
+    },
+    &NeubautenView::PlaylistView(_, _) => None,
+    &NeubautenView::PlaylistPickerView(_, _) => None,
+  }
+}
+
+/// Returns the tracks on the selected rows of the `view` (see
+/// `List::get_selected_items`) that are tracks.
+fn get_selected_tracks(view: &NeubautenView) -> Vec<rustify::Track> {
+  return match view {
+    &NeubautenView::TrackView(_, ref list) => list.get_selected_items(),
+    &NeubautenView::SearchView(_, ref list) => list.get_selected_items(),
+    &NeubautenView::AlbumView(_, ref list) => {
+      list.get_selected_items().into_iter().map(|item| item.track).collect()
+    },
+    &NeubautenView::ArtistView(_, ref list) => {
+      list.get_selected_items().into_iter().filter_map(|item| {
+        match item {
+          view::ArtistItem::TopTrack(track) => Some(track),
+          _ => None,
+        }
+      }).collect()
+    },
+    &NeubautenView::StatsView(_, ref list) => stats_tracks(list.get_selected_items()),
+    &NeubautenView::PlaylistView(_, _) => Vec::new(),
+    &NeubautenView::PlaylistPickerView(_, _) => Vec::new(),
+  }
+}
+
+/// Stops any visual selection and removes all marks in the `view`. Returns
+/// whether or not there was anything selected.
+fn clear_selection(view: &mut NeubautenView) -> bool {
+  return match view {
+    &mut NeubautenView::TrackView(_, ref mut list) => list.clear_selection(),
+    &mut NeubautenView::PlaylistView(_, ref mut list) => list.clear_selection(),
+    &mut NeubautenView::SearchView(_, ref mut list) => list.clear_selection(),
+    &mut NeubautenView::AlbumView(_, ref mut list) => list.clear_selection(),
+    &mut NeubautenView::ArtistView(_, ref mut list) => list.clear_selection(),
+    &mut NeubautenView::PlaylistPickerView(_, ref mut list) => list.clear_selection(),
+    &mut NeubautenView::StatsView(_, ref mut list) => list.clear_selection(),
+  }
+}
+
+/// Returns the tracks on all of the rows in the `view` matching the current
+/// filter, or all of the tracks in the selected playlist in the `PlaylistView`.
+fn get_matching_tracks(view: &NeubautenView) -> Vec<rustify::Track> {
+  return match view {
+    &NeubautenView::TrackView(_, ref list) => list.get_matching_items(),
+    &NeubautenView::SearchView(_, ref list) => list.get_matching_items(),
+    &NeubautenView::AlbumView(_, ref list) => {
+      list.get_matching_items().into_iter().map(|item| item.track).collect()
+    },
+    &NeubautenView::ArtistView(_, ref list) => {
+      list.get_matching_items().into_iter().filter_map(|item| {
+        match item {
+          view::ArtistItem::TopTrack(track) => Some(track),
+          _ => None,
+        }
+      }).collect()
+    },
+    &NeubautenView::PlaylistView(_, ref list) => {
+      match list.get_selected_item() {
+        playlist_tree::Row::Playlist { playlist, .. } => playlist.tracks(),
+        playlist_tree::Row::Folder { .. } => Vec::new(),
+      }
+    },
+    &NeubautenView::StatsView(_, ref list) => stats_tracks(list.get_matching_items()),
+    &NeubautenView::PlaylistPickerView(_, _) => Vec::new(),
+  }
+}
+
+/// Returns the tracks (that could be found) among the rows of the `StatsView`.
+fn stats_tracks(items: Vec<view::StatsItem>) -> Vec<rustify::Track> {
+  return items.into_iter().filter_map(|item| {
+    match item {
+      view::StatsItem::Track(_, _, track) => track,
+      view::StatsItem::Artist(..) => None,
+    }
+  }).collect();
+}
+
+/// Shows the play counts and such (as configured) in the `view`, if it's a
+/// list of tracks.
+fn show_play_columns(view: &mut NeubautenView, settings: configuration::ListSettings, play_log: &plays::PlayLog) {
+  match view {
+    &mut NeubautenView::TrackView(_, ref mut list) => play_log.show_columns(list, settings, view::track_uri),
+    &mut NeubautenView::SearchView(_, ref mut list) => play_log.show_columns(list, settings, view::track_uri),
+    &mut NeubautenView::AlbumView(_, ref mut list) => play_log.show_columns(list, settings, view::album_track_uri),
+    _ => (),
+  }
+}
+
+/// Reloads the items of views showing data that might have changed while we
+/// were in another view.
+fn refresh_view(view: &mut NeubautenView, session: &rustify::Session) {
+  match view {
+    &mut NeubautenView::TrackView(ref playlist, ref mut list) => list.set_items(playlist.tracks()),
+    &mut NeubautenView::PlaylistView(ref mut tree, ref mut list) => {
+      tree.set_entries(playlist_entries(session));
+      list.set_items(tree.rows());
+    },
+    _ => (),
+  }
+}
+
+fn playlist_entries(session: &rustify::Session) -> Vec<playlist_tree::Entry<rustify::Playlist>> {
+  return session.playlist_container_items().into_iter().map(|item| {
+    match item {
+      rustify::PlaylistContainerItem::Playlist(playlist) => playlist_tree::Entry::Playlist(playlist),
+      rustify::PlaylistContainerItem::StartFolder(id, name) => playlist_tree::Entry::StartFolder(id, name),
+      rustify::PlaylistContainerItem::EndFolder(_) => playlist_tree::Entry::EndFolder,
+      rustify::PlaylistContainerItem::Placeholder => playlist_tree::Entry::Placeholder,
+    }
+  }).collect();
+}
+
+fn playlist_view(session: &rustify::Session, height: usize) -> NeubautenView {
+  let tree = playlist_tree::PlaylistTree::new(playlist_entries(session));
+  let list = list::List::new(tree.rows(), height - 2);
+
+  return NeubautenView::PlaylistView(tree, list);
+}
+
+fn album_view(session: &rustify::Session, album: &rustify::Album, height: usize) -> NeubautenView {
+  let browse = session.browse_album(album);
+  let tracks: Vec<view::AlbumTrack> = browse.tracks().into_iter().map(|track| {
+    view::AlbumTrack { track: track }
+  }).collect();
+
+  let list = list::List::new(tracks, height - 3);
+  return NeubautenView::AlbumView(browse, list);
+}
+
+fn artist_view(session: &rustify::Session, artist: &rustify::Artist, height: usize) -> NeubautenView {
+  let browse = session.browse_artist(artist);
+  let items = view::artist_items(&browse);
+
+  let list = list::List::new(items, height - 3);
+  return NeubautenView::ArtistView(browse, list);
+}
+
 fn main() {
-  // Create the configuration directory (if it doesn't exist)
-  std::fs::create_dir_all(configuration::root_dir()).unwrap();
+  let arguments = match cli::parse(std::env::args().skip(1)) {
+    Ok(arguments) => arguments,
+    Err(e) => {
+      let _ = writeln!(&mut std::io::stderr(), "{}\nRun `neubauten --help` for the usage.", e);
+      std::process::exit(2);
+    },
+  };
+
+  match arguments.command {
+    cli::Command::Help => {
+      print!("{}", cli::USAGE);
+      return;
+    },
+    cli::Command::Version => {
+      println!("neubauten {}", env!("CARGO_PKG_VERSION"));
+      return;
+    },
+    cli::Command::PrintDefaultConfig => {
+      print!("{}", configuration::DEFAULT_CONFIG);
+      return;
+    },
+    cli::Command::Ctl(ref args) => {
+      let response = control::request_from_args(args).and_then(|request| {
+        control::send(&configuration::socket_path(), &request)
+      });
+
+      match response {
+        Ok(ref response) if !control::is_error(response) => println!("{}", response),
+        Ok(response) | Err(response) => {
+          let _ = writeln!(&mut std::io::stderr(), "{}", response);
+          std::process::exit(1);
+        },
+      }
+
+      return;
+    },
+    _ => (),
+  }
+
+  // Read the configuration before taking over the terminal, so that any
+  // errors can be printed
+  let mut config = match configuration::load(arguments.config_path.clone()) {
+    Ok(config) => config,
+    Err(e) => {
+      let _ = writeln!(&mut std::io::stderr(), "{}", e);
+      std::process::exit(1);
+    },
+  };
 
   let player = Arc::new(Mutex::new(rustify::OpenALPlayer::new()));
-  let spotify_path = configuration::spotify_path();
   let (mut session, receiver) = rustify::Session::new(
     get_application_key(),
-    &spotify_path,
-    &spotify_path,
+    &config.cache_path.to_string_lossy(),
+    &config.settings_path.to_string_lossy(),
     "neubauten",
     player,
   );
 
-  // Sign in to Spotify
-  let credentials = configuration::read_credentials();
-  session.login(&credentials.username, &credentials.password);
+  let mut config_watcher = configuration::ConfigWatcher::new(&config);
 
-  // Wait until we've signed in
-  loop {
-    let event = receiver.recv().unwrap();
+  // Forget the credentials libspotify remembers from the last sign in
+  if arguments.command == cli::Command::Logout {
+    session.forget_me();
+    println!("Forgot the remembered credentials");
+    return;
+  }
 
-    if let rustify::Event::LoggedIn = event {
-      break
-    }
+  if let Err(e) = config.resolve_password() {
+    let _ = writeln!(&mut std::io::stderr(), "{}", e);
+    std::process::exit(1);
+  }
+
+  apply_playback_config(&config, &mut session);
+  let username = arguments.username.clone().or(config.username.clone());
+
+  if arguments.command != cli::Command::Interface || arguments.no_tui {
+    let exit_code = run_without_interface(arguments.command, &mut session, &receiver, username, config.password.clone());
+    std::process::exit(exit_code);
   }
 
   let rustbox = match RustBox::init(Default::default()) {
@@ -103,12 +369,20 @@ fn main() {
     Result::Err(e) => panic!("{}", e),
   };
 
-  let height = rustbox.height();
-  let playlists = session.playlists();
+  // Sign in to Spotify, asking for the credentials if we're missing any
+  let warnings = config.credential_warnings();
 
-  let initial_view: NeubautenView = NeubautenView::PlaylistView(
-    list::List::new(playlists, height - 2)
-  );
+  match login::login(&mut session, &receiver, &rustbox, username, config.password.clone(), &warnings) {
+    Some(username) => {
+      if config.username.is_none() && arguments.username.is_none() {
+        config.save_username(&username);
+      }
+    },
+    None => return,
+  }
+
+  let height = rustbox.height();
+  let initial_view: NeubautenView = playlist_view(&session, height);
 
   // Keep around views we have been on when going into another so that we can
   // easily implement back later on.
@@ -116,30 +390,83 @@ fn main() {
   views.push(initial_view);
 
   let mut playback_queue: Vec<rustify::Track> = Vec::new();
+  let mut event_hooks = hooks::Hooks::new();
+  let mut status_file = status_file::StatusFile::new();
+  let mut notifications = notifications::Notifications::new();
+  let mut scrobbler = scrobble::Scrobbler::new();
+  let mut play_log = plays::PlayLog::load(config.play_log_path());
+  let mut list_settings = config.lists;
   let mut current_track: Option<PlaybackTrack> = None;
-  let mut command_parser = CommandParser::new();
+  let mut play_generation: u64 = 0;
+  let mut message: Option<String> = None;
+
+  // Let other programs (e.g. `neubauten ctl` or media keys) control the
+  // player, by sending the actions to carry out to the event loop
+  let (request_sender, requests) = std::sync::mpsc::channel::<control::Request>();
+
+  let _control_socket = match control::ControlSocket::listen(&configuration::socket_path(), request_sender.clone()) {
+    Ok(control_socket) => Some(control_socket),
+    Err(e) => {
+      message = Some(e);
+      None
+    },
+  };
+
+  if let Some(ref address) = config.mpd_address {
+    if let Err(e) = mpd::listen(address, request_sender.clone()) {
+      message = Some(e);
+    }
+  }
+
+  let mpris = match mpris::Mpris::start(request_sender) {
+    Ok(mpris) => Some(mpris),
+    Err(e) => {
+      message = Some(e);
+      None
+    },
+  };
+
+  let mut command_parser = CommandParser::with_histories(
+    History::load(config.history_path("filter")),
+    History::load(config.history_path("search")),
+    History::load(config.history_path("command")),
+  );
+  command_parser.set_key_bindings(config.keys.clone());
 
   // Listen to events
   loop {
     let mut current_view: NeubautenView = views.pop().unwrap();
     let mut next_view: Option<NeubautenView> = None;
+    let mut search_results: Vec<control::TrackInfo> = Vec::new();
+    let mut error: Option<String> = None;
+
+    let request = requests.try_recv().ok();
+    let next_action: Action = match request {
+      Some(ref request) => request.action.clone(),
+      None => get_next_action(&receiver, &rustbox, &mut command_parser),
+    };
+    let mut close_view: bool = next_action == Action::Back;
 
-    let next_action: Action = get_next_action(&receiver, &rustbox, &mut command_parser);
+    if next_action != Action::Noop && next_action != Action::PlayNextTrack {
+      message = None;
+    }
 
     // Update the view
     rustbox.clear();
-    print_view(&mut current_view, &rustbox);
-    print_status_bar(&current_track, session.is_playing(), &rustbox);
-    print_command_bar(&command_parser, &rustbox);
+    print_view(&mut current_view, &config.theme, &rustbox);
+    print_status_bar(&current_track, session.is_playing(), &config.theme, &rustbox);
+    print_command_bar(&command_parser, &message, &rustbox);
     rustbox.present();
 
     // Process that action
     match next_action {
       Action::Select => {
+        let mut toggle_folder: Option<u64> = None;
+
         match &current_view {
           &NeubautenView::TrackView(ref playlist, ref list) => {
             let track = list.get_selected_item();
-            session.play_track(&track);
+            play_track(&mut session, &track, &mut play_generation);
 
             let playlist_track = PlaybackTrack::PlaylistTrack(
               playlist.clone(),
@@ -151,43 +478,104 @@ fn main() {
           },
           &NeubautenView::SearchView(_, ref list) => {
             let track = list.get_selected_item();
-            session.play_track(&track);
+            play_track(&mut session, &track, &mut play_generation);
 
             let search_track = PlaybackTrack::SearchTrack(track);
 
             current_track = Some(search_track);
           },
-          &NeubautenView::PlaylistView(ref list) => {
+          &NeubautenView::PlaylistView(_, ref list) => {
+            match list.get_selected_item() {
+              playlist_tree::Row::Playlist { playlist, .. } => {
+                let tracks = playlist.tracks();
+
+                let next_list = list::List::new(tracks, height - 2);
+                next_view = Some(NeubautenView::TrackView(playlist, next_list));
+              },
+              playlist_tree::Row::Folder { id, .. } => {
+                toggle_folder = Some(id);
+              },
+            }
+          },
+          &NeubautenView::AlbumView(ref browse, ref list) => {
+            let track_index = list.get_selected_index();
+            play_track(&mut session, &list.get_selected_item().track, &mut play_generation);
+
+            current_track = Some(PlaybackTrack::AlbumTrack(browse.tracks(), track_index));
+          },
+          &NeubautenView::ArtistView(_, ref list) => {
+            match list.get_selected_item() {
+              view::ArtistItem::TopTrack(track) => {
+                play_track(&mut session, &track, &mut play_generation);
+                current_track = Some(PlaybackTrack::ArtistTrack(track));
+              },
+              view::ArtistItem::Album(album) | view::ArtistItem::Single(album) => {
+                next_view = Some(album_view(&session, &album, height));
+              },
+              view::ArtistItem::RelatedArtist(artist) => {
+                next_view = Some(artist_view(&session, &artist, height));
+              },
+            }
+          },
+          &NeubautenView::PlaylistPickerView(ref tracks, ref list) => {
             let playlist = list.get_selected_item();
-            let tracks = playlist.tracks();
+            playlist.add_tracks(tracks, playlist.num_tracks());
 
-            let next_list = list::List::new(tracks, height - 2);
-            next_view = Some(NeubautenView::TrackView(playlist, next_list));
+            message = Some(format!("Added {} track(s) to {}", tracks.len(), playlist));
+            close_view = true;
+          },
+          &NeubautenView::StatsView(_, ref list) => {
+            match list.get_selected_item() {
+              view::StatsItem::Track(_, _, Some(track)) => {
+                play_track(&mut session, &track, &mut play_generation);
+                current_track = Some(PlaybackTrack::StatsTrack(track));
+              },
+              view::StatsItem::Track(_, play, None) => {
+                message = Some(format!("{} can't be found on Spotify", play.label()));
+              },
+              view::StatsItem::Artist(_, artist) => {
+                let search_result = session.search(&artist);
+                let list = list::List::new(search_result.tracks(), height - 2);
+                next_view = Some(NeubautenView::SearchView(search_result, list));
+              },
+            }
           },
         }
+
+        if let (Some(id), &mut NeubautenView::PlaylistView(ref mut tree, ref mut list)) = (toggle_folder, &mut current_view) {
+          tree.toggle_folder(id);
+          list.set_items(tree.rows());
+        }
       },
       Action::PlayNextTrack => {
         if !playback_queue.is_empty() {
           let next_track = playback_queue.remove(0);
-          session.play_track(&next_track);
+          play_track(&mut session, &next_track, &mut play_generation);
           current_track = Some(PlaybackTrack::QueueTrack(next_track));
         } else {
           match current_track {
             Some(t) => {
               match t {
-                PlaybackTrack::PlaylistTrack(playlist, _, track_index) => {
-                  let next_track = playlist.track((track_index + 1) as i32);
+                PlaybackTrack::PlaylistTrack(playlist, track, track_index) => {
+                  // When the playing track has been removed from the playlist
+                  // the track that took its place is the next one
+                  let next_index = match playlist.track(track_index as i32) {
+                    Some(ref t) if t.uri() == track.uri() => track_index + 1,
+                    _ => track_index,
+                  };
+
+                  let next_track = playlist.track(next_index as i32);
 
                   // Pick the next track in the playlist and play that
                   match next_track {
                     Some(track) => {
-                      session.play_track(&track);
+                      play_track(&mut session, &track, &mut play_generation);
 
                       current_track = Some(
                         PlaybackTrack::PlaylistTrack(
                           playlist.clone(),
                           track,
-                          track_index + 1,
+                          next_index,
                         )
                       );
                     },
@@ -196,6 +584,15 @@ fn main() {
                     }
                   }
                 },
+                PlaybackTrack::AlbumTrack(tracks, track_index) => {
+                  // Continue with the next track on the album, if any
+                  if track_index + 1 < tracks.len() {
+                    play_track(&mut session, &tracks[track_index + 1], &mut play_generation);
+                    current_track = Some(PlaybackTrack::AlbumTrack(tracks, track_index + 1));
+                  } else {
+                    current_track = None;
+                  }
+                },
                 _ => {
                   current_track = None;
                 },
@@ -205,56 +602,419 @@ fn main() {
           }
         }
       },
+      Action::PlayPreviousTrack => {
+        // Go back in the playlist or album, anything else starts over
+        current_track = match current_track.take() {
+          Some(PlaybackTrack::PlaylistTrack(playlist, track, track_index)) => {
+            let previous_track = if track_index > 0 { playlist.track((track_index - 1) as i32) } else { None };
+
+            match previous_track {
+              Some(previous_track) => Some(PlaybackTrack::PlaylistTrack(playlist, previous_track, track_index - 1)),
+              None => Some(PlaybackTrack::PlaylistTrack(playlist, track, track_index)),
+            }
+          },
+          Some(PlaybackTrack::AlbumTrack(tracks, track_index)) => {
+            Some(PlaybackTrack::AlbumTrack(tracks, track_index.saturating_sub(1)))
+          },
+          current_track => current_track,
+        };
+
+        if let Some(ref current_track) = current_track {
+          play_track(&mut session, current_track.track(), &mut play_generation);
+        }
+      },
+      Action::Seek(offset) => {
+        if let Some(ref current_track) = current_track {
+          let position = session.position();
+          let position = (position.as_secs() * 1000) as i64 + (position.subsec_nanos() / 1_000_000) as i64 + offset;
+          let length = current_track.track().duration().as_secs() as i64 * 1000;
+
+          // Seeking past either end stops at the start or end of the track
+          let position = std::time::Duration::from_millis(std::cmp::min(std::cmp::max(position, 0), length) as u64);
+          session.seek(position);
+
+          if let Some(ref mpris) = mpris {
+            mpris.seeked(position);
+          }
+        }
+      },
+      Action::QueueUri(ref uri) => {
+        match session.track_from_uri(uri) {
+          Some(track) => {
+            message = Some(format!("Queued {}", track));
+            playback_queue.push(track);
+          },
+          None => {
+            error = Some(format!("{} is not a Spotify track", uri));
+            message = error.clone();
+          },
+        }
+      },
       Action::MoveUp => {
         match &mut current_view {
           &mut NeubautenView::TrackView(_, ref mut list) => list.handle_up(),
-          &mut NeubautenView::PlaylistView(ref mut list) => list.handle_up(),
+          &mut NeubautenView::PlaylistView(_, ref mut list) => list.handle_up(),
           &mut NeubautenView::SearchView(_, ref mut list) => list.handle_up(),
+          &mut NeubautenView::AlbumView(_, ref mut list) => list.handle_up(),
+          &mut NeubautenView::ArtistView(_, ref mut list) => list.handle_up(),
+          &mut NeubautenView::PlaylistPickerView(_, ref mut list) => list.handle_up(),
+          &mut NeubautenView::StatsView(_, ref mut list) => list.handle_up(),
         }
       },
       Action::MoveDown => {
         match &mut current_view {
           &mut NeubautenView::TrackView(_, ref mut list) => list.handle_down(),
-          &mut NeubautenView::PlaylistView(ref mut list) => list.handle_down(),
+          &mut NeubautenView::PlaylistView(_, ref mut list) => list.handle_down(),
           &mut NeubautenView::SearchView(_, ref mut list) => list.handle_down(),
+          &mut NeubautenView::AlbumView(_, ref mut list) => list.handle_down(),
+          &mut NeubautenView::ArtistView(_, ref mut list) => list.handle_down(),
+          &mut NeubautenView::PlaylistPickerView(_, ref mut list) => list.handle_down(),
+          &mut NeubautenView::StatsView(_, ref mut list) => list.handle_down(),
         }
       },
       Action::MoveTop => {
         match &mut current_view {
           &mut NeubautenView::TrackView(_, ref mut list) => list.handle_top(),
-          &mut NeubautenView::PlaylistView(ref mut list) => list.handle_top(),
+          &mut NeubautenView::PlaylistView(_, ref mut list) => list.handle_top(),
           &mut NeubautenView::SearchView(_, ref mut list) => list.handle_top(),
+          &mut NeubautenView::AlbumView(_, ref mut list) => list.handle_top(),
+          &mut NeubautenView::ArtistView(_, ref mut list) => list.handle_top(),
+          &mut NeubautenView::PlaylistPickerView(_, ref mut list) => list.handle_top(),
+          &mut NeubautenView::StatsView(_, ref mut list) => list.handle_top(),
         }
       },
       Action::MoveBottom => {
         match &mut current_view {
           &mut NeubautenView::TrackView(_, ref mut list) => list.handle_bottom(),
-          &mut NeubautenView::PlaylistView(ref mut list) => list.handle_bottom(),
+          &mut NeubautenView::PlaylistView(_, ref mut list) => list.handle_bottom(),
           &mut NeubautenView::SearchView(_, ref mut list) => list.handle_bottom(),
+          &mut NeubautenView::AlbumView(_, ref mut list) => list.handle_bottom(),
+          &mut NeubautenView::ArtistView(_, ref mut list) => list.handle_bottom(),
+          &mut NeubautenView::PlaylistPickerView(_, ref mut list) => list.handle_bottom(),
+          &mut NeubautenView::StatsView(_, ref mut list) => list.handle_bottom(),
         }
       },
       Action::QueueTrack => {
+        playback_queue.extend(get_selected_tracks(&current_view));
+        clear_selection(&mut current_view);
+      },
+      Action::QueueNext => {
+        let tracks = get_selected_tracks(&current_view);
+        for (index, track) in tracks.into_iter().enumerate() {
+          playback_queue.insert(index, track);
+        }
+
+        clear_selection(&mut current_view);
+      },
+      Action::ReplaceQueue => {
+        let tracks = get_selected_tracks(&current_view);
+
+        if !tracks.is_empty() {
+          playback_queue = tracks;
+
+          let next_track = playback_queue.remove(0);
+          play_track(&mut session, &next_track, &mut play_generation);
+          current_track = Some(PlaybackTrack::QueueTrack(next_track));
+        }
+
+        clear_selection(&mut current_view);
+      },
+      Action::ToggleMark => {
         match &mut current_view {
-          &mut NeubautenView::TrackView(_, ref mut list) => {
-            let track = list.get_selected_item();
-            playback_queue.push(track);
+          &mut NeubautenView::TrackView(_, ref mut list) => list.toggle_mark(),
+          &mut NeubautenView::SearchView(_, ref mut list) => list.toggle_mark(),
+          &mut NeubautenView::AlbumView(_, ref mut list) => list.toggle_mark(),
+          &mut NeubautenView::ArtistView(_, ref mut list) => list.toggle_mark(),
+          &mut NeubautenView::StatsView(_, ref mut list) => list.toggle_mark(),
+          _ => (),
+        }
+      },
+      Action::MarkAll => {
+        match &mut current_view {
+          &mut NeubautenView::TrackView(_, ref mut list) => list.mark_all_matching(),
+          &mut NeubautenView::SearchView(_, ref mut list) => list.mark_all_matching(),
+          &mut NeubautenView::AlbumView(_, ref mut list) => list.mark_all_matching(),
+          &mut NeubautenView::ArtistView(_, ref mut list) => list.mark_all_matching(),
+          &mut NeubautenView::StatsView(_, ref mut list) => list.mark_all_matching(),
+          _ => (),
+        }
+      },
+      Action::InvertMarks => {
+        match &mut current_view {
+          &mut NeubautenView::TrackView(_, ref mut list) => list.invert_marks(),
+          &mut NeubautenView::SearchView(_, ref mut list) => list.invert_marks(),
+          &mut NeubautenView::AlbumView(_, ref mut list) => list.invert_marks(),
+          &mut NeubautenView::ArtistView(_, ref mut list) => list.invert_marks(),
+          &mut NeubautenView::StatsView(_, ref mut list) => list.invert_marks(),
+          _ => (),
+        }
+      },
+      Action::ToggleVisual => {
+        match &mut current_view {
+          &mut NeubautenView::TrackView(_, ref mut list) => list.toggle_visual(),
+          &mut NeubautenView::SearchView(_, ref mut list) => list.toggle_visual(),
+          &mut NeubautenView::AlbumView(_, ref mut list) => list.toggle_visual(),
+          &mut NeubautenView::ArtistView(_, ref mut list) => list.toggle_visual(),
+          &mut NeubautenView::StatsView(_, ref mut list) => list.toggle_visual(),
+          _ => (),
+        }
+      },
+      Action::QueueAll => {
+        let tracks = get_matching_tracks(&current_view);
+
+        message = Some(format!("Queued {} track(s)", tracks.len()));
+        playback_queue.extend(tracks);
+      },
+      Action::SaveQueue(ref name, include_current_track) => {
+        let mut tracks: Vec<rustify::Track> = Vec::new();
+        if include_current_track {
+          if let Some(ref t) = current_track {
+            tracks.push(t.track().clone());
+          }
+        }
+
+        tracks.extend(playback_queue.iter().cloned());
+
+        if tracks.is_empty() {
+          message = Some("There are no tracks in the queue to save".to_string());
+        } else {
+          message = match session.create_playlist(name) {
+            Some(playlist) => {
+              playlist.add_tracks(&tracks, 0);
+              Some(format!("Saved {} track(s) to {}", tracks.len(), name))
+            },
+            None => Some(format!("Couldn't create playlist {}", name)),
+          };
+        }
+
+        refresh_view(&mut current_view, &session);
+      },
+      Action::AddToPlaylist => {
+        let tracks = get_selected_tracks(&current_view);
+
+        if !tracks.is_empty() {
+          // Other users' playlists can only be added to when they're
+          // collaborative
+          let username = session.user_name();
+          let playlists: Vec<rustify::Playlist> = session.playlists().into_iter().filter(|playlist| {
+            playlist.is_collaborative() || playlist.owner() == username
+          }).collect();
+
+          if playlists.is_empty() {
+            message = Some("There are no playlists to add to".to_string());
+          } else {
+            let list = list::List::new(playlists, height - 3);
+            next_view = Some(NeubautenView::PlaylistPickerView(tracks, list));
+          }
+        }
+
+        clear_selection(&mut current_view);
+      },
+      Action::RemoveTrack => {
+        if let &NeubautenView::TrackView(ref playlist, ref list) = &current_view {
+          if !list.is_empty() {
+            let question = format!("Remove {} from {}? (y/n)", list.get_selected_item(), playlist);
+            command_parser.confirm(question, Action::Confirmed(Box::new(Action::RemoveTrack)));
+          }
+        }
+      },
+      Action::MoveTrackDown => {
+        if let &mut NeubautenView::TrackView(ref playlist, ref mut list) = &mut current_view {
+          // The tracks next to it in a sorted list (or one only showing the
+          // unplayed tracks) aren't the ones next to it in the playlist
+          if list.is_rearranged() {
+            message = Some("Tracks can't be moved while the list is sorted or filtered by :unplayed".to_string());
+          } else if !list.is_empty() {
+            let track_index = list.get_selected_index();
+
+            if track_index + 1 < playlist.num_tracks() as usize {
+              // The new position is the index to move to *before* the track
+              // has been removed from its old position
+              playlist.reorder_tracks(&[track_index as i32], (track_index + 2) as i32);
+              update_track_index(&mut current_track, playlist, |index| swap_index(index, track_index, track_index + 1));
+              list.set_items(playlist.tracks());
+              list.select_index(track_index + 1);
+            }
+          }
+        }
+      },
+      Action::MoveTrackUp => {
+        if let &mut NeubautenView::TrackView(ref playlist, ref mut list) = &mut current_view {
+          if list.is_rearranged() {
+            message = Some("Tracks can't be moved while the list is sorted or filtered by :unplayed".to_string());
+          } else if !list.is_empty() {
+            let track_index = list.get_selected_index();
+
+            if track_index > 0 {
+              playlist.reorder_tracks(&[track_index as i32], (track_index - 1) as i32);
+              update_track_index(&mut current_track, playlist, |index| swap_index(index, track_index, track_index - 1));
+              list.set_items(playlist.tracks());
+              list.select_index(track_index - 1);
+            }
+          }
+        }
+      },
+      Action::Confirmed(ref confirmed_action) => {
+        match (&**confirmed_action, &mut current_view) {
+          (&Action::RemoveTrack, &mut NeubautenView::TrackView(ref playlist, ref mut list)) => {
+            if !list.is_empty() {
+              let removed_index = list.get_selected_index();
+              playlist.remove_tracks(&[removed_index as i32]);
+
+              // A removed track keeps its index, where the track that followed
+              // it is now
+              update_track_index(&mut current_track, playlist, |index| {
+                if index > removed_index { index - 1 } else { index }
+              });
+
+              list.set_items(playlist.tracks());
+            }
           },
-          &mut NeubautenView::SearchView(_, ref mut list) => {
-            let track = list.get_selected_item();
-            playback_queue.push(track);
+          (&Action::DeletePlaylist, &mut NeubautenView::PlaylistView(_, ref list)) => {
+            if !list.is_empty() {
+              session.remove_playlist(list.get_selected_item().index() as i32);
+            }
           },
           _ => (),
         }
+
+        refresh_view(&mut current_view, &session);
+      },
+      Action::CreatePlaylist(ref name) => {
+        message = match session.create_playlist(name) {
+          Some(_) => Some(format!("Created playlist {}", name)),
+          None => Some(format!("Couldn't create playlist {}", name)),
+        };
+
+        refresh_view(&mut current_view, &session);
+      },
+      Action::CreateFolder(ref name) => {
+        let index = session.playlist_container_items().len();
+
+        message = match session.add_folder(index as i32, name) {
+          true => Some(format!("Created folder {}", name)),
+          false => Some(format!("Couldn't create folder {}", name)),
+        };
+
+        refresh_view(&mut current_view, &session);
+      },
+      Action::RenamePlaylist(ref name) => {
+        match &current_view {
+          &NeubautenView::PlaylistView(_, ref list) if list.is_empty() => {
+            message = Some("Select a playlist to rename".to_string());
+          },
+          &NeubautenView::PlaylistView(_, ref list) => {
+            match list.get_selected_item() {
+              playlist_tree::Row::Playlist { playlist, .. } => playlist.rename(name),
+              playlist_tree::Row::Folder { .. } => {
+                message = Some("Folders can't be renamed".to_string());
+              },
+            }
+          },
+          &NeubautenView::TrackView(ref playlist, _) => playlist.rename(name),
+          _ => {
+            message = Some("Select a playlist to rename".to_string());
+          },
+        }
+
+        refresh_view(&mut current_view, &session);
+      },
+      Action::DeletePlaylist => {
+        if let &NeubautenView::PlaylistView(_, ref list) = &current_view {
+          if !list.is_empty() {
+            let question = match list.get_selected_item() {
+              playlist_tree::Row::Playlist { playlist, .. } => format!("Delete playlist {}? (y/n)", playlist),
+              playlist_tree::Row::Folder { name, .. } => format!("Delete folder {}? (y/n)", name),
+            };
+
+            command_parser.confirm(question, Action::Confirmed(Box::new(Action::DeletePlaylist)));
+          }
+        }
+      },
+      Action::InvalidCommand(ref error) => {
+        message = Some(error.clone());
+      },
+      Action::ReloadConfig => {
+        message = Some(reload_config(&mut config, &mut session, &mut command_parser));
+        config_watcher = configuration::ConfigWatcher::new(&config);
+      },
+      Action::SortList(key) => {
+        match &mut current_view {
+          &mut NeubautenView::TrackView(_, ref mut list) => play_log.sort_list(list, key, view::track_uri),
+          &mut NeubautenView::SearchView(_, ref mut list) => play_log.sort_list(list, key, view::track_uri),
+          &mut NeubautenView::AlbumView(_, ref mut list) => play_log.sort_list(list, key, view::album_track_uri),
+          _ => message = Some("Only lists of tracks can be sorted".to_string()),
+        }
+      },
+      Action::ShowUnplayed => {
+        let has_unplayed = match &mut current_view {
+          &mut NeubautenView::TrackView(_, ref mut list) => play_log.hide_played(list, view::track_uri),
+          &mut NeubautenView::SearchView(_, ref mut list) => play_log.hide_played(list, view::track_uri),
+          &mut NeubautenView::AlbumView(_, ref mut list) => play_log.hide_played(list, view::album_track_uri),
+          _ => {
+            message = Some("Only lists of tracks can be filtered".to_string());
+            true
+          },
+        };
+
+        if !has_unplayed {
+          message = Some("Every track in the list has been played".to_string());
+        }
+      },
+      Action::ShowStats(period) => {
+        let items = view::stats_items(&session, play_log.plays(), period, plays::unix_time());
+
+        if items.is_empty() {
+          message = Some(format!("Nothing has been played during {}", period.description()));
+        } else {
+          let list = list::List::new(items, height - 3);
+          next_view = Some(NeubautenView::StatsView(period, list));
+        }
+      },
+      Action::GoToAlbum => {
+        if let Some(track) = get_selected_track(&current_view) {
+          next_view = Some(album_view(&session, &track.album(), height));
+        }
+      },
+      Action::GoToArtist => {
+        // Tracks with several artists goes to the first (main) one
+        let artist = get_selected_track(&current_view).and_then(|track| {
+          track.artists().into_iter().next()
+        });
+
+        if let Some(artist) = artist {
+          next_view = Some(artist_view(&session, &artist, height));
+        }
       },
       Action::TogglePlayback => {
         let is_playing = session.is_playing();
         session.toggle_playback(!is_playing);
       },
+      Action::Pause => {
+        if session.is_playing() {
+          session.toggle_playback(false);
+        }
+      },
+      Action::Resume => {
+        if current_track.is_some() {
+          if !session.is_playing() {
+            session.toggle_playback(true);
+          }
+        } else if !playback_queue.is_empty() {
+          let next_track = playback_queue.remove(0);
+          play_track(&mut session, &next_track, &mut play_generation);
+          current_track = Some(PlaybackTrack::QueueTrack(next_track));
+        }
+      },
       Action::FilterList(ref filter) => {
         match &mut current_view {
           &mut NeubautenView::TrackView(_, ref mut list) => list.set_filter(filter),
-          &mut NeubautenView::PlaylistView(ref mut list) => list.set_filter(filter),
+          &mut NeubautenView::PlaylistView(_, ref mut list) => list.set_filter(filter),
           &mut NeubautenView::SearchView(_, ref mut list) => list.set_filter(filter),
+          &mut NeubautenView::AlbumView(_, ref mut list) => list.set_filter(filter),
+          &mut NeubautenView::ArtistView(_, ref mut list) => list.set_filter(filter),
+          &mut NeubautenView::PlaylistPickerView(_, ref mut list) => list.set_filter(filter),
+          &mut NeubautenView::StatsView(_, ref mut list) => list.set_filter(filter),
         }
       },
       Action::SearchTrack(ref query) => {
@@ -264,11 +1024,23 @@ fn main() {
         let list = list::List::new(tracks, height - 2);
         next_view = Some(NeubautenView::SearchView(search_result, list));
       },
+      Action::FindTracks(ref query) => {
+        search_results = session.search(query).tracks().iter().map(control::TrackInfo::new).collect();
+      },
       Action::Back => {
-        match &mut current_view {
-          &mut NeubautenView::TrackView(_, ref mut list) => list.clear_filter(),
-          &mut NeubautenView::PlaylistView(ref mut list) => list.clear_filter(),
-          &mut NeubautenView::SearchView(_, ref mut list) => list.clear_filter(),
+        // Esc with a selection only clears the selection
+        if clear_selection(&mut current_view) {
+          close_view = false;
+        } else {
+          match &mut current_view {
+            &mut NeubautenView::TrackView(_, ref mut list) => list.clear_filter(),
+            &mut NeubautenView::PlaylistView(_, ref mut list) => list.clear_filter(),
+            &mut NeubautenView::SearchView(_, ref mut list) => list.clear_filter(),
+            &mut NeubautenView::AlbumView(_, ref mut list) => list.clear_filter(),
+            &mut NeubautenView::ArtistView(_, ref mut list) => list.clear_filter(),
+            &mut NeubautenView::PlaylistPickerView(_, ref mut list) => list.clear_filter(),
+            &mut NeubautenView::StatsView(_, ref mut list) => list.clear_filter(),
+          }
         }
       },
       Action::Quit => {
@@ -277,55 +1049,222 @@ fn main() {
       _ => (),
     }
 
-    if next_action != Action::Back || views.len() == 0 {
+    if !close_view || views.len() == 0 {
       views.push(current_view);
+    } else if let Some(view) = views.last_mut() {
+      refresh_view(view, &session);
+    }
+
+    if let Some(mut next_view) = next_view {
+      show_play_columns(&mut next_view, list_settings, &play_log);
+      views.push(next_view);
+    }
+
+    if let Some(request) = request {
+      let _ = request.reply.send(control::Status {
+        track: current_track.as_ref().map(|track| control::TrackInfo::new(track.track())),
+        playing: session.is_playing(),
+        position: session.position(),
+        queue: playback_queue.iter().map(control::TrackInfo::new).collect(),
+        message: message.clone(),
+        search_results: search_results,
+        error: error,
+      });
+    }
+
+    if let Some(ref mpris) = mpris {
+      mpris.update(current_track.as_ref().map(|track| track.track()), session.is_playing(), session.position());
+    }
+
+    event_hooks.update(&config.hooks, current_track.as_ref().map(|track| track.track()), play_generation, session.is_playing());
+
+    notifications.update(&config.notifications, current_track.as_ref().map(|track| track.track()), play_generation);
+
+    scrobbler.update(&config, current_track.as_ref().map(|track| track.track()), play_generation, session.is_playing());
+
+    if let Some(e) = scrobbler.error() {
+      message = Some(e);
+    }
+
+    play_log.update(current_track.as_ref().map(|track| track.track()),
+                    play_generation,
+                    current_track.as_ref().and_then(|track| track.playlist()),
+                    session.is_playing());
+
+    if let Some(ref path) = config.status_file.path {
+      status_file.update(path,
+                         &config.status_file.format,
+                         current_track.as_ref().map(|track| track.track()),
+                         session.is_playing(),
+                         session.position());
+    }
+
+    if config.watch && config_watcher.has_changed() {
+      message = Some(reload_config(&mut config, &mut session, &mut command_parser));
     }
 
-    if next_view.is_some() {
-      views.push(next_view.unwrap());
+    if config.lists != list_settings {
+      list_settings = config.lists;
+
+      for view in views.iter_mut() {
+        show_play_columns(view, list_settings, &play_log);
+      }
     }
   }
 }
 
-fn print_view(view: &mut NeubautenView, rustbox: &rustbox::RustBox) {
+/// Runs a command given on the command line (or just stays signed in with
+/// `--no-tui`) without taking over the terminal.
+///
+/// Returns the exit code.
+fn run_without_interface(command: cli::Command,
+                         session: &mut rustify::Session,
+                         receiver: &std::sync::mpsc::Receiver<rustify::Event>,
+                         username: Option<String>,
+                         password: Option<String>) -> i32 {
+  let username = match login::login_on_terminal(session, receiver, username, password) {
+    Ok(username) => username,
+    Err(e) => {
+      let _ = writeln!(&mut std::io::stderr(), "{}", e);
+      return 1;
+    },
+  };
+
+  let result = match command {
+    cli::Command::Play(ref uri) => cli::play(session, receiver, uri),
+    cli::Command::Search(ref query) => {
+      cli::search(session, query);
+      Ok(())
+    },
+    cli::Command::Playlists => {
+      cli::print_playlists(playlist_entries(session));
+      Ok(())
+    },
+    _ => {
+      let _ = writeln!(&mut std::io::stderr(), "Signed in as {}, running without the interface", username);
+      cli::keep_running(receiver);
+      Ok(())
+    },
+  };
+
+  return match result {
+    Ok(_) => 0,
+    Err(e) => {
+      let _ = writeln!(&mut std::io::stderr(), "{}", e);
+      1
+    },
+  }
+}
+
+fn apply_playback_config(config: &configuration::Config, session: &mut rustify::Session) {
+  session.set_preferred_bitrate(config.playback.bitrate);
+  session.set_volume_normalization(config.playback.volume_normalization);
+}
+
+/// Reads the configuration file again and applies it (the theme is applied
+/// the next time the screen is drawn). If the file can't be used the current
+/// configuration is kept.
+///
+/// Returns a message for the command bar.
+fn reload_config(config: &mut configuration::Config,
+                 session: &mut rustify::Session,
+                 command_parser: &mut CommandParser) -> String {
+  return match config.reload() {
+    Ok(new_config) => {
+      *config = new_config;
+      apply_playback_config(config, session);
+      command_parser.set_key_bindings(config.keys.clone());
+      format!("Reloaded {}", config.path().display())
+    },
+    Err(e) => e.to_string(),
+  }
+}
+
+/// Updates the index of the playing track after tracks have been removed from
+/// or moved within the `playlist`, if that's where it's played from. The
+/// `new_index` of a track is worked out from its old one.
+fn update_track_index<F: Fn(usize) -> usize>(current_track: &mut Option<PlaybackTrack>,
+                                             playlist: &rustify::Playlist,
+                                             new_index: F) {
+  if let &mut Some(PlaybackTrack::PlaylistTrack(ref playing_from, _, ref mut track_index)) = current_track {
+    if playing_from == playlist {
+      *track_index = new_index(*track_index);
+    }
+  }
+}
+
+/// Returns where the track at `index` ends up when the tracks at `a` and `b`
+/// trade places.
+fn swap_index(index: usize, a: usize, b: usize) -> usize {
+  if index == a {
+    return b;
+  } else if index == b {
+    return a;
+  }
+
+  return index;
+}
+
+/// Starts playing the `track`. The `play_generation` is counted up with every
+/// track started, so that the modules following the playback (e.g. the hooks)
+/// can tell a track that is played again from one that is still playing.
+fn play_track(session: &mut rustify::Session, track: &rustify::Track, play_generation: &mut u64) {
+  session.play_track(track);
+  *play_generation += 1;
+}
+
+fn print_view(view: &mut NeubautenView, theme: &configuration::Theme, rustbox: &rustbox::RustBox) {
   match view {
-    &mut NeubautenView::PlaylistView(ref mut list) => {
-      list.print(&rustbox, 0, 0, false);
+    &mut NeubautenView::PlaylistView(_, ref mut list) => {
+      list.print(&rustbox, theme, 0, 0, false);
     },
     &mut NeubautenView::TrackView(_, ref mut list) => {
-      list.print(&rustbox, 0, 0, false);
+      list.print(&rustbox, theme, 0, 0, false);
     },
     &mut NeubautenView::SearchView(_, ref mut list) => {
-      list.print(&rustbox, 0, 0, false);
-    }
+      list.print(&rustbox, theme, 0, 0, false);
+    },
+    &mut NeubautenView::AlbumView(ref browse, ref mut list) => {
+      let album = browse.album();
+      let header = format!(" {} ({}) ", album, album.year());
+
+      rustbox.print(0, 0, rustbox::RB_BOLD, Color::Default, Color::Default, &header);
+      list.print(&rustbox, theme, 0, 1, false);
+    },
+    &mut NeubautenView::ArtistView(ref browse, ref mut list) => {
+      let header = format!(" {} ", browse.artist());
+
+      rustbox.print(0, 0, rustbox::RB_BOLD, Color::Default, Color::Default, &header);
+      list.print(&rustbox, theme, 0, 1, false);
+    },
+    &mut NeubautenView::PlaylistPickerView(ref tracks, ref mut list) => {
+      let header = format!(" Add {} track(s) to playlist: ", tracks.len());
+
+      rustbox.print(0, 0, rustbox::RB_BOLD, Color::Default, Color::Default, &header);
+      list.print(&rustbox, theme, 0, 1, false);
+    },
+    &mut NeubautenView::StatsView(period, ref mut list) => {
+      let header = format!(" Most played during {} ", period.description());
+
+      rustbox.print(0, 0, rustbox::RB_BOLD, Color::Default, Color::Default, &header);
+      list.print(&rustbox, theme, 0, 1, false);
+    },
   }
 }
 
 fn print_status_bar(current_track: &Option<PlaybackTrack>,
                     playing: bool,
+                    theme: &configuration::Theme,
                     rustbox: &rustbox::RustBox) {
   let y_pos = rustbox.height() - 2;
   let width = rustbox.width();
 
   let playback: String = match current_track {
     &Some(ref t) => {
-      match t {
-        &PlaybackTrack::QueueTrack(ref track) => {
-          let minutes: u64 = track.duration().as_secs() / 60;
-          let seconds: u64 = track.duration().as_secs() % 60;
-          format!("Playback: {} [{}:{seconds:>0width$}]", track.to_string(), minutes, seconds=seconds, width=2)
-        },
-        &PlaybackTrack::SearchTrack(ref track) => {
-          let minutes: u64 = track.duration().as_secs() / 60;
-          let seconds: u64 = track.duration().as_secs() % 60;
-          format!("Playback: {} [{}:{seconds:>0width$}]", track.to_string(), minutes, seconds=seconds, width=2)
-        },
-        &PlaybackTrack::PlaylistTrack(_, ref track, _) => {
-          let minutes: u64 = track.duration().as_secs() / 60;
-          let seconds: u64 = track.duration().as_secs() % 60;
-          format!("Playback: {} [{}:{seconds:>0width$}]", track.to_string(), minutes, seconds=seconds, width=2)
-        }
-      }
+      let track = t.track();
+      let minutes: u64 = track.duration().as_secs() / 60;
+      let seconds: u64 = track.duration().as_secs() % 60;
+      format!("Playback: {} [{}:{seconds:>0width$}]", track.to_string(), minutes, seconds=seconds, width=2)
     },
     &None => "Playback: -".to_string(),
   };
@@ -340,19 +1279,65 @@ fn print_status_bar(current_track: &Option<PlaybackTrack>,
     value.push(' ');
   }
 
-  rustbox.print(0, y_pos, rustbox::RB_BOLD, Color::White, Color::Cyan, &value);
+  rustbox.print(0, y_pos, rustbox::RB_BOLD, theme.status_bar.foreground, theme.status_bar.background, &value);
+}
+
+/// Prints `value` like `RustBox::print`, except that double-width characters
+/// take up two cells instead of having the next character drawn over them.
+fn print_wide(x: usize, y: usize, style: rustbox::Style, fg: Color, bg: Color, value: &str,
+              rustbox: &rustbox::RustBox) {
+  let mut x = x;
+
+  for c in value.chars() {
+    rustbox.print_char(x, y, style, fg, bg, c);
+    x += line_editor::display_width(c);
+  }
 }
 
 fn print_command_bar(command_parser: &command_parser::CommandParser,
+                     message: &Option<String>,
                      rustbox: &rustbox::RustBox) {
   let y_pos = rustbox.height() - 1;
 
   let argument = command_parser.get_argument();
-  let value: String = match command_parser.get_argument_type() {
-    command_parser::ArgumentType::FilterArgument => format!("Filter: {}", argument),
-    command_parser::ArgumentType::SearchArgument => format!("Search: {}", argument),
-    _ => "".to_string(),
+
+  let prompt: Option<String> = match command_parser.get_reverse_search_query() {
+    Some(query) => {
+      match command_parser.has_reverse_search_match() {
+        true => Some(format!("(reverse-i-search)`{}': ", query)),
+        false => Some(format!("(failing reverse-i-search)`{}': ", query)),
+      }
+    },
+    None => {
+      match command_parser.get_argument_type() {
+        command_parser::ArgumentType::FilterArgument => Some("Filter: ".to_string()),
+        command_parser::ArgumentType::SearchArgument => Some("Search: ".to_string()),
+        command_parser::ArgumentType::CommandArgument => Some(":".to_string()),
+        _ => None,
+      }
+    },
   };
 
-  rustbox.print(0, y_pos, rustbox::RB_NORMAL, Color::Default, Color::Default, &value);
+  match prompt {
+    Some(prompt) => {
+      let value = format!("{}{}", prompt, argument);
+      print_wide(0, y_pos, rustbox::RB_NORMAL, Color::Default, Color::Default, &value, rustbox);
+
+      let prompt_width: usize = prompt.chars().map(line_editor::display_width).sum();
+      let cursor_x = prompt_width + command_parser.get_argument_cursor();
+      rustbox.set_cursor(cursor_x as isize, y_pos as isize);
+    },
+    None => {
+      // Hides the cursor
+      rustbox.set_cursor(-1, -1);
+
+      let value = match (command_parser.get_confirmation_question(), message) {
+        (Some(question), _) => question,
+        (None, &Some(ref message)) => message.clone(),
+        (None, &None) => "".to_string(),
+      };
+
+      rustbox.print(0, y_pos, rustbox::RB_NORMAL, Color::Default, Color::Default, &value);
+    },
+  }
 }
@@ -0,0 +1,471 @@
+use action::{Action};
+use control;
+use std;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{Sender};
+use std::time::Duration;
+
+/// The version of the protocol told to the clients.
+const VERSION: &'static str = "0.19.0";
+
+// The error codes used in `ACK` responses
+const ERROR_ARGUMENT: u32 = 2;
+const ERROR_UNKNOWN: u32 = 5;
+const ERROR_NO_EXIST: u32 = 50;
+const ERROR_SYSTEM: u32 = 52;
+
+/// A command that failed, answered with an `ACK` line.
+#[derive(Debug, PartialEq)]
+struct Ack {
+  code: u32,
+  message: String,
+}
+
+impl Ack {
+  fn new(code: u32, message: &str) -> Ack {
+    return Ack { code: code, message: message.to_string() };
+  }
+}
+
+/// Listens for MPD clients on `address` (e.g. `127.0.0.1:6600`), turning the
+/// commands they send into actions for the event loop. Only the commands
+/// needed for playing and queueing tracks are understood: `status`,
+/// `currentsong`, `playlistinfo`, `play`, `pause`, `next`, `previous`, `add`
+/// and `search` (plus `ping`, `close` and command lists).
+///
+/// The playlist MPD clients see is the playing track followed by the queue.
+pub fn listen(address: &str, requests: Sender<control::Request>) -> Result<(), String> {
+  let listener = match TcpListener::bind(address) {
+    Ok(listener) => listener,
+    Err(e) => return Err(format!("Unable to listen for MPD clients on {}: {}", address, e)),
+  };
+
+  std::thread::spawn(move || {
+    for stream in listener.incoming() {
+      if let Ok(stream) = stream {
+        let requests = requests.clone();
+        std::thread::spawn(move || handle_connection(stream, requests));
+      }
+    }
+  });
+
+  return Ok(());
+}
+
+fn handle_connection(stream: TcpStream, requests: Sender<control::Request>) {
+  let mut writer = match stream.try_clone() {
+    Ok(writer) => writer,
+    Err(_) => return,
+  };
+
+  if write!(writer, "OK MPD {}\n", VERSION).is_err() {
+    return;
+  }
+
+  // The commands of a command list, and whether every one of them should be
+  // followed by `list_OK`
+  let mut command_list: Option<(Vec<Vec<String>>, bool)> = None;
+
+  for line in BufReader::new(stream).lines() {
+    let line = match line {
+      Ok(line) => line,
+      Err(_) => return,
+    };
+
+    let arguments = match split_arguments(&line) {
+      Ok(arguments) => arguments,
+      Err(e) => {
+        if write!(writer, "{}", ack(&e, 0, "")).is_err() {
+          return;
+        }
+        continue;
+      },
+    };
+
+    let command = arguments.first().cloned().unwrap_or(String::new());
+
+    let response = match (command.as_str(), command_list.take()) {
+      ("close", _) => return,
+      ("command_list_begin", None) => {
+        command_list = Some((Vec::new(), false));
+        continue;
+      },
+      ("command_list_ok_begin", None) => {
+        command_list = Some((Vec::new(), true));
+        continue;
+      },
+      ("command_list_end", Some((commands, list_ok))) => run_command_list(&commands, list_ok, &requests),
+      (_, Some((mut commands, list_ok))) => {
+        commands.push(arguments);
+        command_list = Some((commands, list_ok));
+        continue;
+      },
+      (_, None) => {
+        match execute(&arguments, &requests) {
+          Ok(response) => format!("{}OK\n", response),
+          Err(e) => ack(&e, 0, &command),
+        }
+      },
+    };
+
+    if write!(writer, "{}", response).is_err() {
+      return;
+    }
+  }
+}
+
+/// Runs the commands in order, stopping at the first one that fails.
+fn run_command_list(commands: &[Vec<String>], list_ok: bool, requests: &Sender<control::Request>) -> String {
+  let mut response = String::new();
+
+  for (index, arguments) in commands.iter().enumerate() {
+    match execute(arguments, requests) {
+      Ok(output) => {
+        response.push_str(&output);
+        if list_ok {
+          response.push_str("list_OK\n");
+        }
+      },
+      Err(e) => {
+        response.push_str(&ack(&e, index, &arguments[0]));
+        return response;
+      },
+    }
+  }
+
+  response.push_str("OK\n");
+  return response;
+}
+
+fn ack(error: &Ack, index: usize, command: &str) -> String {
+  return format!("ACK [{}@{}] {{{}}} {}\n", error.code, index, command, error.message);
+}
+
+/// Carries out a command, returning what to answer before the final `OK`.
+fn execute(arguments: &[String], requests: &Sender<control::Request>) -> Result<String, Ack> {
+  let (command, arguments) = match arguments.split_first() {
+    Some((command, arguments)) => (command.as_str(), arguments),
+    None => return Err(Ack::new(ERROR_UNKNOWN, "No command given")),
+  };
+
+  match command {
+    "ping" | "status" | "currentsong" | "playlistinfo" | "play" | "next" | "previous" => {
+      if !arguments.is_empty() && command != "play" {
+        return Err(Ack::new(ERROR_ARGUMENT, "too many arguments"));
+      }
+    },
+    _ => (),
+  }
+
+  return match command {
+    "ping" => Ok(String::new()),
+    "status" => {
+      let status = try!(request(requests, Action::Noop));
+      Ok(format_status(&status))
+    },
+    "currentsong" => {
+      let status = try!(request(requests, Action::Noop));
+      Ok(status.track.as_ref().map_or(String::new(), |track| format_song(track, Some(0))))
+    },
+    "playlistinfo" => {
+      let status = try!(request(requests, Action::Noop));
+      Ok(playlist(&status).iter().enumerate().map(|(index, track)| format_song(track, Some(index))).collect())
+    },
+    // Playing a certain position in the playlist isn't supported, so `play`
+    // always continues with what's playing or next in the queue
+    "play" => request(requests, Action::Resume).map(|_| String::new()),
+    "pause" => {
+      let action = match arguments.first().map(|argument| argument.as_str()) {
+        None => Action::TogglePlayback,
+        Some("1") => Action::Pause,
+        Some("0") => Action::Resume,
+        Some(_) => return Err(Ack::new(ERROR_ARGUMENT, "expected 0 or 1")),
+      };
+
+      request(requests, action).map(|_| String::new())
+    },
+    "next" => request(requests, Action::PlayNextTrack).map(|_| String::new()),
+    "previous" => request(requests, Action::PlayPreviousTrack).map(|_| String::new()),
+    "add" => {
+      if arguments.len() != 1 {
+        return Err(Ack::new(ERROR_ARGUMENT, "expected the URI of a track"));
+      }
+
+      let uri = &arguments[0];
+      let status = try!(request(requests, Action::QueueUri(uri.clone())));
+
+      if let Some(ref message) = status.error {
+        return Err(Ack::new(ERROR_NO_EXIST, message));
+      }
+
+      Ok(String::new())
+    },
+    "search" => {
+      // The arguments are pairs like `artist "Einstürzende Neubauten"`, but
+      // Spotify only has a single search field so the values are joined
+      if arguments.is_empty() || arguments.len() % 2 != 0 {
+        return Err(Ack::new(ERROR_ARGUMENT, "expected pairs of a tag and what to search for"));
+      }
+
+      let values: Vec<String> = arguments.iter()
+        .enumerate()
+        .filter(|&(index, _)| index % 2 == 1)
+        .map(|(_, value)| value.clone())
+        .collect();
+      let status = try!(request(requests, Action::FindTracks(values.join(" "))));
+
+      Ok(status.search_results.iter().map(|track| format_song(track, None)).collect())
+    },
+    _ => Err(Ack::new(ERROR_UNKNOWN, &format!("unknown command \"{}\"", command))),
+  }
+}
+
+/// Sends the action to the event loop and waits for it to be carried out.
+fn request(requests: &Sender<control::Request>, action: Action) -> Result<control::Status, Ack> {
+  let (reply, status) = std::sync::mpsc::channel();
+
+  if requests.send(control::Request { action: action, reply: reply }).is_err() {
+    return Err(Ack::new(ERROR_SYSTEM, "neubauten is shutting down"));
+  }
+
+  return status.recv().map_err(|_| Ack::new(ERROR_SYSTEM, "neubauten is shutting down"));
+}
+
+/// The playing track followed by the queue.
+fn playlist(status: &control::Status) -> Vec<control::TrackInfo> {
+  return status.track.iter().chain(status.queue.iter()).cloned().collect();
+}
+
+fn format_status(status: &control::Status) -> String {
+  let playlist = playlist(status);
+
+  // Clients only read the playlist again when its version changes
+  let mut hasher = DefaultHasher::new();
+  for track in &playlist {
+    track.uri.hash(&mut hasher);
+  }
+
+  let state = match (&status.track, status.playing) {
+    (&Some(_), true) => "play",
+    (&Some(_), false) => "pause",
+    (&None, _) => "stop",
+  };
+
+  // Played tracks are taken off the queue, like MPD does with `consume`
+  let mut response = format!(
+    "volume: -1\nrepeat: 0\nrandom: 0\nsingle: 0\nconsume: 1\nplaylist: {}\nplaylistlength: {}\nstate: {}\n",
+    hasher.finish() as u32,
+    playlist.len(),
+    state,
+  );
+
+  if let Some(ref track) = status.track {
+    response.push_str(&format!(
+      "song: 0\nsongid: 0\ntime: {}:{}\nelapsed: {:.3}\nduration: {:.3}\n",
+      status.position.as_secs(),
+      track.duration.as_secs(),
+      seconds(status.position),
+      seconds(track.duration),
+    ));
+  }
+
+  if playlist.len() > 1 || (status.track.is_none() && !playlist.is_empty()) {
+    let next = if status.track.is_some() { 1 } else { 0 };
+    response.push_str(&format!("nextsong: {}\nnextsongid: {}\n", next, next));
+  }
+
+  return response;
+}
+
+/// Describes a track, with its `position` in the playlist if it's in it.
+fn format_song(track: &control::TrackInfo, position: Option<usize>) -> String {
+  let mut song = format!("file: {}\nTitle: {}\n", track.uri, track.title);
+
+  for artist in &track.artists {
+    song.push_str(&format!("Artist: {}\n", artist));
+  }
+
+  song.push_str(&format!(
+    "Album: {}\nTime: {}\nduration: {:.3}\n",
+    track.album,
+    track.duration.as_secs(),
+    seconds(track.duration),
+  ));
+
+  if let Some(position) = position {
+    song.push_str(&format!("Pos: {}\nId: {}\n", position, position));
+  }
+
+  return song;
+}
+
+fn seconds(duration: Duration) -> f64 {
+  return duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9;
+}
+
+/// Splits a command into its arguments, which are separated by spaces or
+/// quoted like `add "spotify:track:..."` (with `\"` and `\\` inside quotes).
+fn split_arguments(line: &str) -> Result<Vec<String>, Ack> {
+  let mut arguments: Vec<String> = Vec::new();
+  let mut chars = line.trim().chars().peekable();
+
+  loop {
+    while chars.peek() == Some(&' ') || chars.peek() == Some(&'\t') {
+      chars.next();
+    }
+
+    let mut argument = String::new();
+
+    match chars.peek().cloned() {
+      None => return Ok(arguments),
+      Some('"') => {
+        chars.next();
+
+        loop {
+          match chars.next() {
+            Some('"') => break,
+            Some('\\') => {
+              match chars.next() {
+                Some(c) => argument.push(c),
+                None => return Err(Ack::new(ERROR_ARGUMENT, "missing closing '\"'")),
+              }
+            },
+            Some(c) => argument.push(c),
+            None => return Err(Ack::new(ERROR_ARGUMENT, "missing closing '\"'")),
+          }
+        }
+      },
+      Some(_) => {
+        while let Some(c) = chars.peek().cloned() {
+          if c == ' ' || c == '\t' {
+            break;
+          }
+
+          argument.push(c);
+          chars.next();
+        }
+      },
+    }
+
+    arguments.push(argument);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use action::{Action};
+  use control;
+  use std;
+  use std::time::Duration;
+  use test_fixtures::{track};
+
+  fn status(playing: bool) -> control::Status {
+    return control::Status {
+      track: Some(track("spotify:track:1", "Die Interimsliebenden")),
+      playing: playing,
+      position: Duration::from_millis(1500),
+      queue: vec![track("spotify:track:2", "Zebulon")],
+      message: None,
+      search_results: Vec::new(),
+      error: None,
+    };
+  }
+
+  /// Answers every request with `status`, returning the actions that were
+  /// requested once the sender has been dropped.
+  fn event_loop(status: control::Status) -> (std::sync::mpsc::Sender<control::Request>, std::thread::JoinHandle<Vec<Action>>) {
+    let (sender, receiver) = std::sync::mpsc::channel::<control::Request>();
+
+    let handle = std::thread::spawn(move || {
+      let mut actions = Vec::new();
+      for request in receiver.iter() {
+        actions.push(request.action.clone());
+        let _ = request.reply.send(status.clone());
+      }
+
+      return actions;
+    });
+
+    return (sender, handle);
+  }
+
+  fn run(command: &str, status: control::Status) -> (Result<String, Ack>, Vec<Action>) {
+    let (sender, handle) = event_loop(status);
+    let result = execute(&split_arguments(command).unwrap(), &sender);
+    drop(sender);
+
+    return (result, handle.join().unwrap());
+  }
+
+  #[test]
+  fn it_splits_quoted_arguments() {
+    assert_eq!(split_arguments(r#"search artist "Einstürzende \"Neubauten\"""#).unwrap(),
+               vec!["search", "artist", "Einstürzende \"Neubauten\""]);
+    assert_eq!(split_arguments("  status ").unwrap(), vec!["status"]);
+    assert!(split_arguments(r#"add "spotify:track:1"#).is_err());
+  }
+
+  #[test]
+  fn it_answers_status_with_the_playlist_and_position() {
+    let (result, actions) = run("status", status(false));
+    let response = result.unwrap();
+
+    assert!(response.contains("playlistlength: 2\n"));
+    assert!(response.contains("state: pause\n"));
+    assert!(response.contains("time: 1:254\n"));
+    assert!(response.contains("elapsed: 1.500\n"));
+    assert!(response.contains("nextsong: 1\n"));
+    assert_eq!(actions, vec![Action::Noop]);
+  }
+
+  #[test]
+  fn it_lists_the_playing_track_and_queue() {
+    let (result, _) = run("playlistinfo", status(true));
+
+    assert_eq!(result.unwrap(), "\
+file: spotify:track:1\nTitle: Die Interimsliebenden\nArtist: Einstürzende Neubauten\n\
+Album: Tabula Rasa\nTime: 254\nduration: 254.000\nPos: 0\nId: 0\n\
+file: spotify:track:2\nTitle: Zebulon\nArtist: Einstürzende Neubauten\n\
+Album: Tabula Rasa\nTime: 254\nduration: 254.000\nPos: 1\nId: 1\n");
+  }
+
+  #[test]
+  fn it_pauses_and_resumes_without_asking_whether_we_are_playing() {
+    assert_eq!(run("pause 1", status(true)).1, vec![Action::Pause]);
+    assert_eq!(run("pause 0", status(false)).1, vec![Action::Resume]);
+    assert_eq!(run("play", status(false)).1, vec![Action::Resume]);
+    assert_eq!(run("pause", status(false)).1, vec![Action::TogglePlayback]);
+  }
+
+  #[test]
+  fn it_translates_commands_into_actions() {
+    assert_eq!(run("next", status(true)).1, vec![Action::PlayNextTrack]);
+    assert_eq!(run(r#"search artist "Einstürzende Neubauten" album "Tabula Rasa""#, status(true)).1,
+               vec![Action::FindTracks("Einstürzende Neubauten Tabula Rasa".to_string())]);
+  }
+
+  #[test]
+  fn it_rejects_tracks_that_were_not_queued() {
+    // The track is already last in the queue, which mustn't be taken for it
+    // having been queued
+    let mut status = status(true);
+    status.error = Some("spotify:track:2 is not a Spotify track".to_string());
+
+    let (result, actions) = run("add spotify:track:2", status);
+
+    assert_eq!(result.unwrap_err().code, ERROR_NO_EXIST);
+    assert_eq!(actions, vec![Action::QueueUri("spotify:track:2".to_string())]);
+  }
+
+  #[test]
+  fn it_rejects_unknown_commands() {
+    let (result, actions) = run("crossfade 5", status(true));
+
+    assert_eq!(ack(&result.unwrap_err(), 0, "crossfade"), "ACK [5@0] {crossfade} unknown command \"crossfade\"\n");
+    assert!(actions.is_empty());
+  }
+}
@@ -0,0 +1,456 @@
+// Without the `mpris` feature (and libdbus) nothing is exposed on D-Bus.
+#[cfg(feature = "mpris")]
+pub use self::enabled::Mpris;
+
+#[cfg(not(feature = "mpris"))]
+pub use self::disabled::Mpris;
+
+#[cfg(not(feature = "mpris"))]
+mod disabled {
+  use control;
+  use rustify;
+  use std::sync::mpsc::{Sender};
+  use std::time::Duration;
+
+  pub struct Mpris;
+
+  impl Mpris {
+    pub fn start(_: Sender<control::Request>) -> Result<Mpris, String> {
+      return Ok(Mpris);
+    }
+
+    pub fn update(&self, _: Option<&rustify::Track>, _: bool, _: Duration) {
+    }
+
+    pub fn seeked(&self, _: Duration) {
+    }
+  }
+}
+
+#[cfg(feature = "mpris")]
+mod enabled {
+  use action::{Action};
+  use control;
+  use dbus;
+  use dbus::{BusType, Connection, Message, NameFlag};
+  use dbus::arg::{Append, Arg, RefArg, Variant};
+  use dbus::tree::{Access, Factory, Method, MTFn, Property};
+  use libc;
+  use rustify;
+  use std;
+  use std::collections::HashMap;
+  use std::sync::{Arc, Mutex};
+  use std::sync::mpsc::{Sender};
+  use std::time::Duration;
+
+  const BUS_NAME: &'static str = "org.mpris.MediaPlayer2.neubauten";
+  const PATH: &'static str = "/org/mpris/MediaPlayer2";
+  const ROOT_INTERFACE: &'static str = "org.mpris.MediaPlayer2";
+  const PLAYER_INTERFACE: &'static str = "org.mpris.MediaPlayer2.Player";
+
+  type Metadata = HashMap<String, Variant<Box<RefArg>>>;
+
+  /// The track being played, copied out of libspotify so that the D-Bus
+  /// thread can answer without going through the event loop.
+  #[derive(Clone, PartialEq)]
+  struct NowPlaying {
+    uri: String,
+    title: String,
+    artists: Vec<String>,
+    album: String,
+    length: Duration,
+    playing: bool,
+  }
+
+  impl NowPlaying {
+    fn new(track: &rustify::Track, playing: bool) -> NowPlaying {
+      return NowPlaying {
+        uri: track.uri(),
+        title: track.name(),
+        artists: track.artists().iter().map(|artist| artist.name()).collect(),
+        album: track.album().name(),
+        length: track.duration(),
+        playing: playing,
+      };
+    }
+  }
+
+  struct State {
+    now_playing: Option<NowPlaying>,
+    position: Duration,
+
+    /// Where the player has seeked to, until the clients have been told.
+    seeked: Option<Duration>,
+  }
+
+  /// Owns the `org.mpris.MediaPlayer2.neubauten` name. The methods that are
+  /// called are sent as actions to the event loop, which tells what's playing
+  /// through `update`.
+  pub struct Mpris {
+    state: Arc<Mutex<State>>,
+  }
+
+  impl Mpris {
+    pub fn start(requests: Sender<control::Request>) -> Result<Mpris, String> {
+      let state = Arc::new(Mutex::new(State {
+        now_playing: None,
+        position: Duration::from_secs(0),
+        seeked: None,
+      }));
+
+      // The connection can't be moved between threads, so it's made on the
+      // thread that serves it
+      let (started, result) = std::sync::mpsc::channel();
+      let thread_state = state.clone();
+
+      std::thread::spawn(move || {
+        match connect() {
+          Ok(connection) => {
+            let _ = started.send(Ok(()));
+            serve(connection, requests, thread_state);
+          },
+          Err(e) => {
+            let _ = started.send(Err(e));
+          },
+        }
+      });
+
+      return match result.recv() {
+        Ok(Ok(())) => Ok(Mpris { state: state }),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err("Unable to start MPRIS".to_string()),
+      }
+    }
+
+    /// Updates what's playing, which is announced to the clients if it has
+    /// changed.
+    pub fn update(&self, track: Option<&rustify::Track>, playing: bool, position: Duration) {
+      let mut state = self.state.lock().unwrap();
+
+      let changed = match (&state.now_playing, track) {
+        (&Some(ref now_playing), Some(track)) => now_playing.playing != playing || now_playing.uri != track.uri(),
+        (&None, None) => false,
+        _ => true,
+      };
+
+      if changed {
+        state.now_playing = track.map(|track| NowPlaying::new(track, playing));
+      }
+
+      state.position = position;
+    }
+
+    /// Tells the clients that the player has jumped to the `position`, since
+    /// they otherwise assume it keeps moving on from where it was.
+    pub fn seeked(&self, position: Duration) {
+      let mut state = self.state.lock().unwrap();
+      state.position = position;
+      state.seeked = Some(position);
+    }
+  }
+
+  fn connect() -> Result<Connection, String> {
+    let connection = match Connection::get_private(BusType::Session) {
+      Ok(connection) => connection,
+      Err(e) => return Err(format!("Unable to connect to D-Bus: {}", e)),
+    };
+
+    // Another instance may already have the name, in which case this one is
+    // told apart by its process id like the specification suggests
+    let name = match connection.register_name(BUS_NAME, NameFlag::DoNotQueue as u32) {
+      Ok(dbus::RequestNameReply::PrimaryOwner) => return Ok(connection),
+      _ => format!("{}.instance{}", BUS_NAME, unsafe { libc::getpid() }),
+    };
+
+    return match connection.register_name(&name, NameFlag::DoNotQueue as u32) {
+      Ok(_) => Ok(connection),
+      Err(e) => Err(format!("Unable to register {} on D-Bus: {}", name, e)),
+    }
+  }
+
+  fn serve(connection: Connection, requests: Sender<control::Request>, state: Arc<Mutex<State>>) {
+    let f = Factory::new_fn::<()>();
+
+    let root = f.interface(ROOT_INTERFACE, ())
+      .add_m(f.method("Raise", (), |m| Ok(vec![m.msg.method_return()])))
+      .add_m(action_method(&f, "Quit", &requests, Action::Quit))
+      .add_p(constant(&f, "CanQuit", true))
+      .add_p(constant(&f, "CanRaise", false))
+      .add_p(constant(&f, "HasTrackList", false))
+      .add_p(constant(&f, "Identity", "neubauten"))
+      .add_p(constant(&f, "SupportedUriSchemes", vec!["spotify".to_string()]))
+      .add_p(constant(&f, "SupportedMimeTypes", Vec::<String>::new()));
+
+    let status_state = state.clone();
+    let metadata_state = state.clone();
+    let position_state = state.clone();
+
+    let player = f.interface(PLAYER_INTERFACE, ())
+      .add_m(action_method(&f, "PlayPause", &requests, Action::TogglePlayback))
+      .add_m(action_method(&f, "Next", &requests, Action::PlayNextTrack))
+      .add_m(action_method(&f, "Previous", &requests, Action::PlayPreviousTrack))
+      .add_m(action_method(&f, "Play", &requests, Action::Resume))
+      .add_m(action_method(&f, "Pause", &requests, Action::Pause))
+      .add_m(action_method(&f, "Stop", &requests, Action::Pause))
+      .add_m(seek_method(&f, &requests))
+      .add_m(set_position_method(&f, &requests, &state))
+      .add_p(f.property::<&str, _>("PlaybackStatus", ())
+        .access(Access::Read)
+        .on_get(move |iter, _| {
+          iter.append(playback_status(&status_state.lock().unwrap().now_playing));
+          return Ok(());
+        }))
+      .add_p(f.property::<HashMap<&str, Variant<Box<RefArg>>>, _>("Metadata", ())
+        .access(Access::Read)
+        .on_get(move |iter, _| {
+          iter.append(metadata(&metadata_state.lock().unwrap().now_playing));
+          return Ok(());
+        }))
+      .add_p(f.property::<i64, _>("Position", ())
+        .access(Access::Read)
+        .on_get(move |iter, _| {
+          iter.append(microseconds(position_state.lock().unwrap().position));
+          return Ok(());
+        }))
+      .add_p(constant(&f, "Rate", 1.0f64))
+      .add_p(constant(&f, "MinimumRate", 1.0f64))
+      .add_p(constant(&f, "MaximumRate", 1.0f64))
+      .add_p(constant(&f, "CanGoNext", true))
+      .add_p(constant(&f, "CanGoPrevious", true))
+      .add_p(constant(&f, "CanPlay", true))
+      .add_p(constant(&f, "CanPause", true))
+      .add_p(constant(&f, "CanSeek", true))
+      .add_p(constant(&f, "CanControl", true));
+
+    let tree = f.tree(()).add(f.object_path(PATH, ()).introspectable().add(root).add(player));
+
+    if tree.set_registered(&connection, true).is_err() {
+      return;
+    }
+
+    connection.add_handler(tree);
+
+    let mut announced: Option<NowPlaying> = None;
+
+    loop {
+      for _ in connection.incoming(100) {}
+
+      let (now_playing, seeked) = {
+        let mut state = state.lock().unwrap();
+        (state.now_playing.clone(), state.seeked.take())
+      };
+
+      if now_playing != announced {
+        let _ = connection.send(properties_changed(&now_playing));
+        announced = now_playing;
+      }
+
+      if let Some(position) = seeked {
+        let signal = Message::signal(&PATH.into(), &PLAYER_INTERFACE.into(), &"Seeked".into())
+          .append1(microseconds(position));
+        let _ = connection.send(signal);
+      }
+    }
+  }
+
+  /// Sends the action to the event loop. Nobody waits for the status, since
+  /// the D-Bus methods don't return anything.
+  fn send(requests: &Sender<control::Request>, action: Action) {
+    let (reply, _) = std::sync::mpsc::channel();
+    let _ = requests.send(control::Request { action: action, reply: reply });
+  }
+
+  fn action_method(f: &Factory<MTFn<()>, ()>,
+                   name: &'static str,
+                   requests: &Sender<control::Request>,
+                   action: Action) -> Method<MTFn<()>, ()> {
+    let requests = requests.clone();
+
+    return f.method(name, (), move |m| {
+      send(&requests, action.clone());
+      return Ok(vec![m.msg.method_return()]);
+    });
+  }
+
+  /// `Seek` takes the offset in microseconds.
+  fn seek_method(f: &Factory<MTFn<()>, ()>, requests: &Sender<control::Request>) -> Method<MTFn<()>, ()> {
+    let requests = requests.clone();
+
+    return f.method("Seek", (), move |m| {
+      let offset: i64 = try!(m.msg.read1());
+      send(&requests, Action::Seek(offset / 1000));
+      return Ok(vec![m.msg.method_return()]);
+    }).inarg::<i64, _>("Offset");
+  }
+
+  /// `SetPosition` takes the id of the track (so that a track that has
+  /// started since isn't affected) and the position in microseconds.
+  fn set_position_method(f: &Factory<MTFn<()>, ()>,
+                         requests: &Sender<control::Request>,
+                         state: &Arc<Mutex<State>>) -> Method<MTFn<()>, ()> {
+    let requests = requests.clone();
+    let state = state.clone();
+
+    return f.method("SetPosition", (), move |m| {
+      let (track, position): (dbus::Path, i64) = try!(m.msg.read2());
+      let state = state.lock().unwrap();
+
+      if let Some(ref now_playing) = state.now_playing {
+        let is_playing = &*track == track_id(&now_playing.uri);
+
+        // Positions outside of the track are ignored, like the specification
+        // says
+        if is_playing && position >= 0 && position <= microseconds(now_playing.length) {
+          send(&requests, Action::Seek((position - microseconds(state.position)) / 1000));
+        }
+      }
+
+      return Ok(vec![m.msg.method_return()]);
+    }).inarg::<dbus::Path, _>("TrackId").inarg::<i64, _>("Position");
+  }
+
+  fn constant<T: Arg + Append + Clone + 'static>(f: &Factory<MTFn<()>, ()>,
+                                                 name: &'static str,
+                                                 value: T) -> Property<MTFn<()>, ()> {
+    return f.property::<T, _>(name, ())
+      .access(Access::Read)
+      .on_get(move |iter, _| {
+        iter.append(value.clone());
+        return Ok(());
+      });
+  }
+
+  fn properties_changed(now_playing: &Option<NowPlaying>) -> Message {
+    let mut changed: Metadata = HashMap::new();
+    changed.insert("PlaybackStatus".to_string(), Variant(Box::new(playback_status(now_playing).to_string())));
+    changed.insert("Metadata".to_string(), Variant(Box::new(metadata(now_playing))));
+
+    return Message::signal(&PATH.into(), &"org.freedesktop.DBus.Properties".into(), &"PropertiesChanged".into())
+      .append3(PLAYER_INTERFACE, changed, Vec::<String>::new());
+  }
+
+  fn playback_status(now_playing: &Option<NowPlaying>) -> &'static str {
+    return match now_playing {
+      &Some(NowPlaying { playing: true, .. }) => "Playing",
+      &Some(_) => "Paused",
+      &None => "Stopped",
+    }
+  }
+
+  fn metadata(now_playing: &Option<NowPlaying>) -> Metadata {
+    let mut metadata: Metadata = HashMap::new();
+
+    if let &Some(ref now_playing) = now_playing {
+      metadata.insert("mpris:trackid".to_string(), Variant(Box::new(dbus::Path::from(track_id(&now_playing.uri)))));
+      metadata.insert("mpris:length".to_string(), Variant(Box::new(microseconds(now_playing.length))));
+      metadata.insert("xesam:title".to_string(), Variant(Box::new(now_playing.title.clone())));
+      metadata.insert("xesam:artist".to_string(), Variant(Box::new(now_playing.artists.clone())));
+      metadata.insert("xesam:album".to_string(), Variant(Box::new(now_playing.album.clone())));
+      metadata.insert("xesam:url".to_string(), Variant(Box::new(now_playing.uri.clone())));
+    }
+
+    return metadata;
+  }
+
+  /// The track ids have to be D-Bus object paths, so everything but letters
+  /// and digits in the URI is replaced.
+  fn track_id(uri: &str) -> String {
+    let uri: String = uri.chars().map(|c| {
+      match c {
+        'a'...'z' | 'A'...'Z' | '0'...'9' => c,
+        _ => '_',
+      }
+    }).collect();
+    return format!("/org/neubauten/track/{}", uri);
+  }
+
+  fn microseconds(duration: Duration) -> i64 {
+    return (duration.as_secs() * 1_000_000) as i64 + (duration.subsec_nanos() / 1000) as i64;
+  }
+
+  #[cfg(test)]
+  mod tests {
+    use super::*;
+    use action::{Action};
+    use dbus::{BusType, Connection, Message};
+    use dbus::arg::{Variant};
+    use std;
+    use std::time::Duration;
+    use test_fixtures;
+
+    fn now_playing(playing: bool) -> Option<NowPlaying> {
+      return Some(NowPlaying {
+        uri: "spotify:track:6rqhFgbbKwnb9MLmUQDhG6".to_string(),
+        title: "Tabula Rasa".to_string(),
+        artists: vec!["Einstürzende Neubauten".to_string()],
+        album: "Tabula Rasa".to_string(),
+        length: Duration::from_millis(254500),
+        playing: playing,
+      });
+    }
+
+    #[test]
+    fn it_turns_uris_into_object_paths() {
+      assert_eq!(track_id("spotify:track:6rqhFgbbKwnb9MLmUQDhG6"),
+                 "/org/neubauten/track/spotify_track_6rqhFgbbKwnb9MLmUQDhG6");
+    }
+
+    #[test]
+    fn it_tells_the_playback_status() {
+      assert_eq!(playback_status(&now_playing(true)), "Playing");
+      assert_eq!(playback_status(&now_playing(false)), "Paused");
+      assert_eq!(playback_status(&None), "Stopped");
+    }
+
+    #[test]
+    fn it_measures_the_length_in_microseconds() {
+      assert_eq!(microseconds(Duration::from_millis(254500)), 254500000);
+    }
+
+    #[test]
+    fn it_leaves_the_metadata_empty_when_stopped() {
+      assert!(metadata(&None).is_empty());
+      assert_eq!(metadata(&now_playing(true)).len(), 6);
+    }
+
+    fn call(connection: &Connection, message: Message) -> Message {
+      return connection.send_with_reply_and_block(message, 2000).unwrap();
+    }
+
+    fn player_method(method: &str) -> Message {
+      return Message::new_method_call(BUS_NAME, PATH, PLAYER_INTERFACE, method).unwrap();
+    }
+
+    fn player_property(connection: &Connection, property: &str) -> Message {
+      let message = Message::new_method_call(BUS_NAME, PATH, "org.freedesktop.DBus.Properties", "Get").unwrap()
+        .append2(PLAYER_INTERFACE, property);
+      return call(connection, message);
+    }
+
+    #[test]
+    #[ignore] // Starts a dbus-daemon
+    fn it_serves_the_player_on_the_session_bus() {
+      let _bus = test_fixtures::session_bus();
+      let (sender, requests) = std::sync::mpsc::channel();
+      let mpris = Mpris::start(sender).unwrap();
+      mpris.update(None, false, Duration::from_secs(3));
+
+      let connection = Connection::get_private(BusType::Session).unwrap();
+
+      call(&connection, player_method("PlayPause"));
+      assert_eq!(requests.recv_timeout(Duration::from_secs(2)).unwrap().action, Action::TogglePlayback);
+
+      call(&connection, player_method("Seek").append1(-5_000_000i64));
+      assert_eq!(requests.recv_timeout(Duration::from_secs(2)).unwrap().action, Action::Seek(-5000));
+
+      let status = player_property(&connection, "PlaybackStatus");
+      assert_eq!(status.read1::<Variant<&str>>().unwrap().0, "Stopped");
+
+      let metadata = player_property(&connection, "Metadata");
+      assert!(metadata.read1::<Variant<Metadata>>().unwrap().0.is_empty());
+
+      let position = player_property(&connection, "Position");
+      assert_eq!(position.read1::<Variant<i64>>().unwrap().0, 3_000_000);
+    }
+  }
+}
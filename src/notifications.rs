@@ -0,0 +1,187 @@
+// Without the `notifications` feature (and libdbus) nothing is shown.
+#[cfg(feature = "notifications")]
+pub use self::enabled::Notifications;
+
+#[cfg(not(feature = "notifications"))]
+pub use self::disabled::Notifications;
+
+#[cfg(not(feature = "notifications"))]
+mod disabled {
+  use configuration;
+  use rustify;
+
+  pub struct Notifications;
+
+  impl Notifications {
+    pub fn new() -> Notifications {
+      return Notifications;
+    }
+
+    pub fn update(&mut self, _: &configuration::NotificationSettings, _: Option<&rustify::Track>, _: u64) {
+    }
+  }
+}
+
+#[cfg(feature = "notifications")]
+mod enabled {
+  use configuration;
+  use control;
+  use dbus::{BusType, Connection, Message};
+  use dbus::arg::{RefArg, Variant};
+  use rustify;
+  use status_file;
+  use std;
+  use std::collections::HashMap;
+  use std::sync::mpsc::{Receiver, Sender};
+
+  const APPLICATION_NAME: &'static str = "neubauten";
+
+  /// Shows a desktop notification through the notification daemon
+  /// (`org.freedesktop.Notifications`) whenever a track starts playing.
+  pub struct Notifications {
+    /// The play generation of the track that was playing, which changes
+    /// whenever a track is started (even the same one again).
+    generation: Option<u64>,
+
+    /// Sends the title and body of notifications to the thread showing them,
+    /// which is started with the first notification.
+    sender: Option<Sender<(String, String)>>,
+  }
+
+  impl Notifications {
+    pub fn new() -> Notifications {
+      return Notifications { generation: None, sender: None };
+    }
+
+    pub fn update(&mut self, settings: &configuration::NotificationSettings, track: Option<&rustify::Track>, generation: u64) {
+      let generation = track.map(|_| generation);
+      if generation == self.generation {
+        return;
+      }
+
+      self.generation = generation;
+
+      if let (true, Some(track)) = (settings.enabled, track) {
+        let notification = notification(settings, &control::TrackInfo::new(track));
+
+        if self.sender.is_none() {
+          let (sender, receiver) = std::sync::mpsc::channel();
+          std::thread::spawn(move || show_notifications(receiver));
+          self.sender = Some(sender);
+        }
+
+        // The thread stops if it can't connect to D-Bus, in which case it's
+        // tried again with the next track
+        let sent = self.sender.as_ref().map_or(false, |sender| sender.send(notification).is_ok());
+        if !sent {
+          self.sender = None;
+        }
+      }
+    }
+  }
+
+  /// Shows the notifications as they come. Every one replaces the previous
+  /// one, so that skipping through tracks doesn't pile them up.
+  fn show_notifications(receiver: Receiver<(String, String)>) {
+    let connection = match Connection::get_private(BusType::Session) {
+      Ok(connection) => connection,
+      Err(_) => return,
+    };
+
+    let mut id: u32 = 0;
+
+    for (title, body) in receiver.iter() {
+      let message = match Message::new_method_call("org.freedesktop.Notifications",
+                                                   "/org/freedesktop/Notifications",
+                                                   "org.freedesktop.Notifications",
+                                                   "Notify") {
+        Ok(message) => message,
+        Err(_) => return,
+      };
+
+      let hints: HashMap<String, Variant<Box<RefArg>>> = HashMap::new();
+      let message = message
+        .append3(APPLICATION_NAME, id, "")
+        .append3(title, body, Vec::<String>::new())
+        .append2(hints, -1i32);
+
+      if let Ok(reply) = connection.send_with_reply_and_block(message, 2000) {
+        if let Ok(new_id) = reply.read1::<u32>() {
+          id = new_id;
+        }
+      }
+    }
+  }
+
+  /// Fills in the title and body templates.
+  fn notification(settings: &configuration::NotificationSettings, track: &control::TrackInfo) -> (String, String) {
+    return (
+      status_file::format_track(&settings.title, track),
+      status_file::format_track(&settings.body, track),
+    );
+  }
+
+  #[cfg(test)]
+  mod tests {
+    use super::*;
+    use configuration;
+    use dbus::{BusType, Connection, NameFlag};
+    use dbus::tree::{Factory};
+    use std;
+    use std::sync::mpsc::{Sender};
+    use std::time::Duration;
+    use test_fixtures;
+
+    #[test]
+    fn it_fills_in_the_templates() {
+      let track = test_fixtures::track("spotify:track:1", "Zebulon");
+
+      assert_eq!(notification(&configuration::NotificationSettings::default(), &track),
+                 ("Zebulon".to_string(), "Einstürzende Neubauten - Tabula Rasa".to_string()));
+    }
+
+    /// Stands in for the notification daemon, telling which notification
+    /// each one replaces along with its title. Ids are handed out in order.
+    fn notification_daemon(started: Sender<()>, notified: Sender<(u32, String)>) {
+      let connection = Connection::get_private(BusType::Session).unwrap();
+      connection.register_name("org.freedesktop.Notifications", NameFlag::DoNotQueue as u32).unwrap();
+
+      let f = Factory::new_fn::<()>();
+      let notify = f.method("Notify", (), move |m| {
+        let (_, replaces_id, _, title, _): (&str, u32, &str, &str, &str) = try!(m.msg.read5());
+        let _ = notified.send((replaces_id, title.to_string()));
+        return Ok(vec![m.msg.method_return().append1(replaces_id + 1)]);
+      });
+
+      let tree = f.tree(()).add(f.object_path("/org/freedesktop/Notifications", ())
+        .add(f.interface("org.freedesktop.Notifications", ()).add_m(notify)));
+      tree.set_registered(&connection, true).unwrap();
+      connection.add_handler(tree);
+
+      let _ = started.send(());
+
+      loop {
+        for _ in connection.incoming(100) {}
+      }
+    }
+
+    #[test]
+    #[ignore] // Starts a dbus-daemon
+    fn it_replaces_the_previous_notification() {
+      let _bus = test_fixtures::session_bus();
+
+      let (started, daemon) = std::sync::mpsc::channel();
+      let (notified, notifications) = std::sync::mpsc::channel();
+      std::thread::spawn(move || notification_daemon(started, notified));
+      daemon.recv().unwrap();
+
+      let (sender, receiver) = std::sync::mpsc::channel();
+      std::thread::spawn(move || show_notifications(receiver));
+      sender.send(("Zebulon".to_string(), String::new())).unwrap();
+      sender.send(("Die Interimsliebenden".to_string(), String::new())).unwrap();
+
+      assert_eq!(notifications.recv_timeout(Duration::from_secs(2)).unwrap(), (0, "Zebulon".to_string()));
+      assert_eq!(notifications.recv_timeout(Duration::from_secs(2)).unwrap(), (1, "Die Interimsliebenden".to_string()));
+    }
+  }
+}
@@ -0,0 +1,220 @@
+use std;
+use std::collections::HashSet;
+use std::fmt::{self, Display};
+
+/// An entry in the flat list of playlists and folder markers that makes up
+/// the playlist container. The playlists of a folder are found between its
+/// `StartFolder` and `EndFolder`.
+#[derive(Clone)]
+pub enum Entry<P> {
+  Playlist(P),
+
+  /// Contains the folder id and name
+  StartFolder(u64, String),
+
+  EndFolder,
+
+  /// Anything in the container that we can't show, e.g. a playlist that isn't
+  /// available. Kept around so that the indexes match the container.
+  Placeholder,
+}
+
+/// A row in the `PlaylistView`.
+#[derive(Clone)]
+pub enum Row<P> {
+  Folder {
+    id: u64,
+    name: String,
+    depth: usize,
+    collapsed: bool,
+
+    /// Index of the `StartFolder` in the container.
+    index: usize,
+  },
+
+  Playlist {
+    playlist: P,
+    depth: usize,
+
+    /// Index of the playlist in the container.
+    index: usize,
+  },
+}
+
+impl<P> Row<P> {
+  pub fn index(&self) -> usize {
+    return match self {
+      &Row::Folder { index, .. } => index,
+      &Row::Playlist { index, .. } => index,
+    }
+  }
+}
+
+impl<P: Display> Display for Row<P> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    return match self {
+      &Row::Folder { ref name, depth, collapsed, .. } => {
+        let marker = if collapsed { "+" } else { "-" };
+        write!(f, "{}{} {}/", indentation(depth), marker, name)
+      },
+      &Row::Playlist { ref playlist, depth, .. } => {
+        write!(f, "{}{}", indentation(depth), playlist)
+      },
+    }
+  }
+}
+
+fn indentation(depth: usize) -> String {
+  return std::iter::repeat("  ").take(depth).collect();
+}
+
+/// The playlists and folders of the playlist container together with which of
+/// the folders are collapsed.
+pub struct PlaylistTree<P> {
+  entries: Vec<Entry<P>>,
+  collapsed: HashSet<u64>,
+}
+
+impl<P: Clone> PlaylistTree<P> {
+  pub fn new(entries: Vec<Entry<P>>) -> Self {
+    return PlaylistTree {
+      entries: entries,
+      collapsed: HashSet::new(),
+    };
+  }
+
+  /// Replaces the entries (e.g. after the container has been changed) while
+  /// keeping the collapsed folders collapsed.
+  pub fn set_entries(&mut self, entries: Vec<Entry<P>>) {
+    self.entries = entries;
+  }
+
+  pub fn toggle_folder(&mut self, id: u64) {
+    if !self.collapsed.remove(&id) {
+      self.collapsed.insert(id);
+    }
+  }
+
+  /// Returns the rows to show, leaving out everything inside of collapsed
+  /// folders.
+  pub fn rows(&self) -> Vec<Row<P>> {
+    let mut rows: Vec<Row<P>> = Vec::new();
+    let mut depth: usize = 0;
+
+    // The depth of the outermost collapsed folder we're in, if any
+    let mut hidden_below: Option<usize> = None;
+
+    for (index, entry) in self.entries.iter().enumerate() {
+      match entry {
+        &Entry::StartFolder(id, ref name) => {
+          let collapsed = self.collapsed.contains(&id);
+
+          if hidden_below.is_none() {
+            rows.push(Row::Folder {
+              id: id,
+              name: name.clone(),
+              depth: depth,
+              collapsed: collapsed,
+              index: index,
+            });
+
+            if collapsed {
+              hidden_below = Some(depth);
+            }
+          }
+
+          depth += 1;
+        },
+        &Entry::EndFolder => {
+          depth = depth.saturating_sub(1);
+
+          if hidden_below == Some(depth) {
+            hidden_below = None;
+          }
+        },
+        &Entry::Playlist(ref playlist) => {
+          if hidden_below.is_none() {
+            rows.push(Row::Playlist {
+              playlist: playlist.clone(),
+              depth: depth,
+              index: index,
+            });
+          }
+        },
+        &Entry::Placeholder => (),
+      }
+    }
+
+    return rows;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn entries() -> Vec<Entry<&'static str>> {
+    return vec![
+      Entry::Playlist("Starred"),
+      Entry::StartFolder(1, "Krautrock".to_string()),
+      Entry::Playlist("Can"),
+      Entry::StartFolder(2, "Neu!".to_string()),
+      Entry::Playlist("Neu! 75"),
+      Entry::EndFolder,
+      Entry::EndFolder,
+      Entry::Placeholder,
+      Entry::Playlist("Einstürzende Neubauten"),
+    ];
+  }
+
+  fn row_names(tree: &PlaylistTree<&'static str>) -> Vec<String> {
+    return tree.rows().iter().map(|row| row.to_string()).collect();
+  }
+
+  #[test]
+  fn it_indents_the_contents_of_folders() {
+    let tree = PlaylistTree::new(entries());
+
+    assert_eq!(row_names(&tree), vec![
+      "Starred",
+      "- Krautrock/",
+      "  Can",
+      "  - Neu!/",
+      "    Neu! 75",
+      "Einstürzende Neubauten",
+    ]);
+  }
+
+  #[test]
+  fn it_hides_the_contents_of_collapsed_folders() {
+    let mut tree = PlaylistTree::new(entries());
+
+    tree.toggle_folder(2);
+    tree.toggle_folder(1);
+
+    assert_eq!(row_names(&tree), vec![
+      "Starred",
+      "+ Krautrock/",
+      "Einstürzende Neubauten",
+    ]);
+  }
+
+  #[test]
+  fn it_expands_a_folder_when_toggled_twice() {
+    let mut tree = PlaylistTree::new(entries());
+
+    tree.toggle_folder(2);
+    tree.toggle_folder(2);
+
+    assert_eq!(tree.rows().len(), 6);
+  }
+
+  #[test]
+  fn it_keeps_the_container_index_of_each_row() {
+    let tree = PlaylistTree::new(entries());
+
+    let indexes: Vec<usize> = tree.rows().iter().map(|row| row.index()).collect();
+
+    assert_eq!(indexes, vec![0, 1, 2, 3, 4, 8]);
+  }
+}
@@ -0,0 +1,611 @@
+use configuration;
+use control;
+use list;
+use rustc_serialize::json::{Json};
+use rustify;
+use std;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How much less than the whole track has to be listened to for it to count
+/// as completed rather than skipped.
+const END_MARGIN: u64 = 10;
+
+/// A track that has been played.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Play {
+  pub uri: String,
+  pub title: String,
+  pub artists: Vec<String>,
+  pub album: String,
+
+  /// In seconds.
+  pub duration: u64,
+
+  /// When the track started playing, in seconds since the Unix epoch.
+  pub timestamp: u64,
+
+  /// The name of the playlist the track was played from, if any.
+  pub playlist: Option<String>,
+
+  /// Whether something else was played (or the player was closed) before the
+  /// whole track had been listened to.
+  pub skipped: bool,
+}
+
+impl Play {
+  fn to_json(&self) -> Json {
+    let mut object: BTreeMap<String, Json> = BTreeMap::new();
+    object.insert("uri".to_string(), Json::String(self.uri.clone()));
+    object.insert("title".to_string(), Json::String(self.title.clone()));
+    object.insert("artists".to_string(), Json::Array(self.artists.iter().map(|artist| Json::String(artist.clone())).collect()));
+    object.insert("album".to_string(), Json::String(self.album.clone()));
+    object.insert("duration".to_string(), Json::U64(self.duration));
+    object.insert("timestamp".to_string(), Json::U64(self.timestamp));
+    object.insert("playlist".to_string(), self.playlist.clone().map_or(Json::Null, Json::String));
+    object.insert("skipped".to_string(), Json::Boolean(self.skipped));
+
+    return Json::Object(object);
+  }
+
+  fn from_json(json: &Json) -> Option<Play> {
+    let string = |name: &str| json.find(name).and_then(|value| value.as_string()).map(|value| value.to_string());
+    let number = |name: &str| json.find(name).and_then(|value| value.as_u64());
+
+    let artists: Option<Vec<String>> = json.find("artists").and_then(|value| value.as_array()).map(|artists| {
+      artists.iter().filter_map(|artist| artist.as_string()).map(|artist| artist.to_string()).collect()
+    });
+
+    return match (string("uri"), string("title"), artists, string("album"), number("duration"), number("timestamp")) {
+      (Some(uri), Some(title), Some(artists), Some(album), Some(duration), Some(timestamp)) => {
+        Some(Play {
+          uri: uri,
+          title: title,
+          artists: artists,
+          album: album,
+          duration: duration,
+          timestamp: timestamp,
+          playlist: string("playlist"),
+          skipped: json.find("skipped").and_then(|value| value.as_boolean()).unwrap_or(false),
+        })
+      },
+      _ => None,
+    }
+  }
+
+  /// The title and artists, like tracks are shown in the lists.
+  pub fn label(&self) -> String {
+    return format!("{} - {}", self.title, self.artists.join(", "));
+  }
+}
+
+/// What the log says about a track.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TrackStats {
+  /// The number of times the track has been played, not counting skips.
+  pub plays: usize,
+
+  /// When the track was last played (skipped or not), if ever.
+  pub last_played: Option<u64>,
+}
+
+impl TrackStats {
+  fn add(&mut self, play: &Play) {
+    if !play.skipped {
+      self.plays += 1;
+    }
+
+    self.last_played = std::cmp::max(self.last_played, Some(play.timestamp));
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortKey {
+  /// The most played tracks first.
+  Plays,
+
+  /// The most recently played tracks first, and the ones never played last.
+  LastPlayed,
+}
+
+impl SortKey {
+  /// Returns the sort key with the given name, as used by `:sort`.
+  pub fn from_name(name: &str) -> Option<SortKey> {
+    return match name {
+      "plays" => Some(SortKey::Plays),
+      "last_played" => Some(SortKey::LastPlayed),
+      _ => None,
+    }
+  }
+}
+
+/// The track that is playing, which is added to the log once something else
+/// starts playing.
+struct Current {
+  play: Play,
+
+  /// The time spent playing (but not paused), so that seeking towards the
+  /// end doesn't count as listening.
+  listened: Duration,
+
+  /// When the time was last added up, and whether the track was playing
+  /// since then.
+  ticked_at: Instant,
+  playing: bool,
+}
+
+/// Every track that has been played, oldest first. The plays are kept in a
+/// file with one JSON object per line, which is only ever appended to.
+pub struct PlayLog {
+  plays: Vec<Play>,
+  current: Option<Current>,
+
+  /// The play generation of the `current` track (see `update`).
+  generation: Option<u64>,
+
+  /// The stats of every track in the log by URI, which is shared with the
+  /// lists showing them so that they're always up to date.
+  stats: Rc<RefCell<HashMap<String, TrackStats>>>,
+
+  /// Where new plays gets appended. An in-memory log has no path.
+  path: Option<PathBuf>,
+}
+
+impl PlayLog {
+  pub fn new() -> PlayLog {
+    return PlayLog {
+      plays: Vec::new(),
+      current: None,
+      generation: None,
+      stats: Rc::new(RefCell::new(HashMap::new())),
+      path: None,
+    };
+  }
+
+  /// Reads the plays stored in the file at `path`, skipping any lines that
+  /// can't be read. The file is created with the first play.
+  pub fn load(path: PathBuf) -> PlayLog {
+    let mut log = PlayLog::new();
+
+    if let Ok(file) = std::fs::File::open(&path) {
+      log.plays = BufReader::new(file).lines()
+        .filter_map(|line| line.ok())
+        .filter_map(|line| Json::from_str(&line).ok())
+        .filter_map(|json| Play::from_json(&json))
+        .collect();
+    }
+
+    {
+      let mut stats = log.stats.borrow_mut();
+      for play in &log.plays {
+        stats.entry(play.uri.clone()).or_insert(TrackStats::default()).add(play);
+      }
+    }
+
+    log.path = Some(path);
+    return log;
+  }
+
+  pub fn plays(&self) -> &[Play] {
+    return &self.plays;
+  }
+
+  /// Shows the configured columns in front of the items of the `list`,
+  /// where `uri` returns the URI of the track of an item.
+  pub fn show_columns<T>(&self, list: &mut list::List<T>, settings: configuration::ListSettings, uri: fn(&T) -> String)
+    where T: Display + Clone + 'static {
+    if !settings.play_count && !settings.last_played {
+      list.set_details(None);
+      return;
+    }
+
+    let stats = self.stats.clone();
+    list.set_details(Some(Box::new(move |item: &T| {
+      let track_stats = stats.borrow().get(&uri(item)).cloned().unwrap_or(TrackStats::default());
+      columns(track_stats, settings, unix_time())
+    })));
+  }
+
+  /// Sorts the items of the `list` by the `key`, or shows them in their own
+  /// order again without one.
+  pub fn sort_list<T>(&self, list: &mut list::List<T>, key: Option<SortKey>, uri: fn(&T) -> String)
+    where T: Display + Clone + 'static {
+    let key = match key {
+      Some(key) => key,
+      None => {
+        list.clear_sort();
+        return;
+      },
+    };
+
+    let stats = self.stats.clone();
+    list.sort_by(Box::new(move |a: &T, b: &T| {
+      let stats = stats.borrow();
+      let a = stats.get(&uri(a)).cloned().unwrap_or(TrackStats::default());
+      let b = stats.get(&uri(b)).cloned().unwrap_or(TrackStats::default());
+
+      match key {
+        SortKey::Plays => b.plays.cmp(&a.plays),
+        SortKey::LastPlayed => b.last_played.cmp(&a.last_played),
+      }
+    }));
+  }
+
+  /// Only shows the items of the `list` without any plays. Like in the play
+  /// count column skips don't count, so tracks that have only been skipped
+  /// are still shown. Returns false (and leaves the list as it is) when every
+  /// item shown has been played.
+  pub fn hide_played<T>(&self, list: &mut list::List<T>, uri: fn(&T) -> String) -> bool
+    where T: Display + Clone + 'static {
+    let stats = self.stats.clone();
+    let unplayed = move |item: &T| stats.borrow().get(&uri(item)).map_or(true, |stats| stats.plays == 0);
+
+    if !list.get_matching_items().iter().any(|item| unplayed(item)) {
+      return false;
+    }
+
+    list.set_condition(Box::new(unplayed));
+    return true;
+  }
+
+  /// Keeps track of how long the playing `track` has been listened to,
+  /// adding it to the log when another track starts (or the playback stops).
+  /// The `generation` changes whenever a track is started, so that a track
+  /// played again is logged again.
+  pub fn update(&mut self, track: Option<&rustify::Track>, generation: u64, playlist: Option<String>, playing: bool) {
+    let now = Instant::now();
+    let generation = track.map(|_| generation);
+
+    self.advance(playing, now);
+
+    if generation != self.generation {
+      self.generation = generation;
+      self.start(track.map(control::TrackInfo::new), playlist, unix_time(), playing, now);
+    }
+  }
+
+  fn start(&mut self, track: Option<control::TrackInfo>, playlist: Option<String>, timestamp: u64, playing: bool, now: Instant) {
+    self.finish();
+
+    self.current = track.map(|track| {
+      Current {
+        play: Play {
+          uri: track.uri,
+          title: track.title,
+          artists: track.artists,
+          album: track.album,
+          duration: track.duration.as_secs(),
+          timestamp: timestamp,
+          playlist: playlist,
+          skipped: true,
+        },
+        listened: Duration::from_secs(0),
+        ticked_at: now,
+        playing: playing,
+      }
+    });
+  }
+
+  /// Adds the time since the last tick if the track was playing during it,
+  /// and remembers whether it's `playing` from `now` on.
+  fn advance(&mut self, playing: bool, now: Instant) {
+    if let Some(ref mut current) = self.current {
+      if current.playing {
+        current.listened += now.duration_since(current.ticked_at);
+      }
+
+      current.ticked_at = now;
+      current.playing = playing;
+    }
+  }
+
+  /// Adds the playing track to the log.
+  fn finish(&mut self) {
+    let (mut play, listened) = match self.current.take() {
+      Some(current) => (current.play, current.listened),
+      None => return,
+    };
+
+    play.skipped = listened.as_secs() + END_MARGIN < play.duration;
+
+    if let Some(ref path) = self.path {
+      let file = std::fs::OpenOptions::new().create(true).append(true).open(path);
+
+      if let Ok(mut file) = file {
+        let _ = writeln!(file, "{}", play.to_json());
+      }
+    }
+
+    self.stats.borrow_mut().entry(play.uri.clone()).or_insert(TrackStats::default()).add(&play);
+    self.plays.push(play);
+  }
+}
+
+impl Drop for PlayLog {
+  /// Adds the track that was playing when the player was closed, as skipped
+  /// unless all of it had been listened to.
+  fn drop(&mut self) {
+    self.advance(false, Instant::now());
+    self.finish();
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Period {
+  Week,
+  Month,
+  AllTime,
+}
+
+impl Period {
+  /// Returns the period with the given name, as used by `:stats`.
+  pub fn from_name(name: &str) -> Option<Period> {
+    return match name {
+      "week" => Some(Period::Week),
+      "month" => Some(Period::Month),
+      "all" => Some(Period::AllTime),
+      _ => None,
+    }
+  }
+
+  pub fn description(&self) -> &'static str {
+    return match self {
+      &Period::Week => "the last week",
+      &Period::Month => "the last month",
+      &Period::AllTime => "all time",
+    }
+  }
+
+  /// The timestamp the period starts at, if it's `now`.
+  fn start(&self, now: u64) -> u64 {
+    return match self {
+      &Period::Week => now.saturating_sub(7 * 24 * 60 * 60),
+      &Period::Month => now.saturating_sub(30 * 24 * 60 * 60),
+      &Period::AllTime => 0,
+    }
+  }
+}
+
+/// Returns the most played tracks (and how many times they were played)
+/// during the period, most played first. Skipped plays aren't counted.
+pub fn top_tracks(plays: &[Play], period: Period, now: u64, count: usize) -> Vec<(usize, Play)> {
+  let mut tracks: HashMap<&str, (usize, &Play)> = HashMap::new();
+
+  for play in listened(plays, period, now) {
+    let entry = tracks.entry(&play.uri).or_insert((0, play));
+    entry.0 += 1;
+
+    // The latest play has the most up to date names
+    entry.1 = play;
+  }
+
+  let mut tracks: Vec<(usize, Play)> = tracks.into_iter().map(|(_, (plays, play))| (plays, play.clone())).collect();
+  tracks.sort_by(|a, b| (b.0, a.1.label()).cmp(&(a.0, b.1.label())));
+  tracks.truncate(count);
+
+  return tracks;
+}
+
+/// Returns the most played artists during the period like `top_tracks`. A
+/// track with several artists counts for all of them.
+pub fn top_artists(plays: &[Play], period: Period, now: u64, count: usize) -> Vec<(usize, String)> {
+  let mut artists: HashMap<&str, usize> = HashMap::new();
+
+  for play in listened(plays, period, now) {
+    for artist in &play.artists {
+      *artists.entry(artist).or_insert(0) += 1;
+    }
+  }
+
+  let mut artists: Vec<(usize, String)> = artists.into_iter().map(|(artist, plays)| (plays, artist.to_string())).collect();
+  artists.sort_by(|a, b| (b.0, &a.1).cmp(&(a.0, &b.1)));
+  artists.truncate(count);
+
+  return artists;
+}
+
+/// The text of the columns shown in front of a track.
+fn columns(stats: TrackStats, settings: configuration::ListSettings, now: u64) -> String {
+  let mut columns = String::new();
+
+  if settings.play_count {
+    columns.push_str(&format!("{:>4}  ", stats.plays));
+  }
+
+  if settings.last_played {
+    let last_played = match stats.last_played {
+      Some(timestamp) => ago(now.saturating_sub(timestamp)),
+      None => "never".to_string(),
+    };
+
+    columns.push_str(&format!("{:>8}  ", last_played));
+  }
+
+  return columns;
+}
+
+/// Describes how long ago something was, roughly.
+fn ago(seconds: u64) -> String {
+  let minutes = seconds / 60;
+  let hours = minutes / 60;
+  let days = hours / 24;
+
+  return if minutes == 0 {
+    "just now".to_string()
+  } else if hours == 0 {
+    format!("{}m ago", minutes)
+  } else if days == 0 {
+    format!("{}h ago", hours)
+  } else if days < 365 {
+    format!("{}d ago", days)
+  } else {
+    format!("{}y ago", days / 365)
+  }
+}
+
+/// The plays during the period that weren't skipped.
+fn listened<'a>(plays: &'a [Play], period: Period, now: u64) -> Vec<&'a Play> {
+  let start = period.start(now);
+  return plays.iter().filter(|play| !play.skipped && play.timestamp >= start).collect();
+}
+
+pub fn unix_time() -> u64 {
+  return SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use configuration;
+  use list;
+  use std;
+  use std::time::{Duration, Instant};
+  use test_fixtures::{self, track};
+
+  const DAY: u64 = 24 * 60 * 60;
+
+  fn play(uri: &str, artist: &str, timestamp: u64, skipped: bool) -> Play {
+    return Play {
+      uri: uri.to_string(),
+      title: uri.to_string(),
+      artists: vec![artist.to_string()],
+      album: "Tabula Rasa".to_string(),
+      duration: 254,
+      timestamp: timestamp,
+      playlist: None,
+      skipped: skipped,
+    };
+  }
+
+  #[test]
+  fn it_logs_whether_tracks_were_skipped() {
+    let path = test_fixtures::temporary_path("plays");
+    let _ = std::fs::remove_file(&path);
+
+    {
+      let mut log = PlayLog::load(path.clone());
+      let start = Instant::now();
+      let at = |seconds: u64| start + Duration::from_secs(seconds);
+
+      // Paused time doesn't count, but listening to all of the track does
+      log.start(Some(track("spotify:track:1", "Zebulon")), Some("Favourites".to_string()), 1000, true, at(0));
+      log.advance(false, at(100));
+      log.advance(true, at(200));
+      log.advance(true, at(350));
+
+      log.start(Some(track("spotify:track:2", "Blume")), None, 1350, true, at(350));
+      log.advance(true, at(380));
+
+      log.start(None, None, 1380, false, at(380));
+
+      assert_eq!(log.plays().len(), 2);
+    }
+
+    let log = PlayLog::load(path.clone());
+    let _ = std::fs::remove_file(&path);
+
+    let plays = log.plays();
+    assert_eq!(plays.len(), 2);
+    assert_eq!(plays[0].title, "Zebulon");
+    assert_eq!(plays[0].timestamp, 1000);
+    assert_eq!(plays[0].playlist, Some("Favourites".to_string()));
+    assert_eq!(plays[0].skipped, false);
+    assert_eq!(plays[1].playlist, None);
+    assert_eq!(plays[1].skipped, true);
+  }
+
+  #[test]
+  fn it_counts_the_plays_during_the_period() {
+    let now = 100 * DAY;
+    let plays = vec![
+      play("spotify:track:1", "Einstürzende Neubauten", now - 60 * DAY, false),
+      play("spotify:track:1", "Einstürzende Neubauten", now - 20 * DAY, false),
+      play("spotify:track:2", "Blixa Bargeld", now - 2 * DAY, false),
+      play("spotify:track:2", "Blixa Bargeld", now - DAY, true),
+      play("spotify:track:3", "Einstürzende Neubauten", now - DAY, false),
+    ];
+
+    let tracks = top_tracks(&plays, Period::Week, now, 10);
+    assert_eq!(tracks.iter().map(|&(count, ref play)| (count, play.uri.as_str())).collect::<Vec<_>>(),
+               vec![(1, "spotify:track:2"), (1, "spotify:track:3")]);
+
+    let tracks = top_tracks(&plays, Period::AllTime, now, 1);
+    assert_eq!(tracks.iter().map(|&(count, ref play)| (count, play.uri.as_str())).collect::<Vec<_>>(),
+               vec![(2, "spotify:track:1")]);
+
+    assert_eq!(top_artists(&plays, Period::Month, now, 10),
+               vec![(2, "Einstürzende Neubauten".to_string()), (1, "Blixa Bargeld".to_string())]);
+  }
+
+  fn log(plays: Vec<Play>) -> PlayLog {
+    let mut log = PlayLog::new();
+
+    // Like `finish` does for every play
+    for play in plays {
+      log.stats.borrow_mut().entry(play.uri.clone()).or_insert(TrackStats::default()).add(&play);
+      log.plays.push(play);
+    }
+
+    return log;
+  }
+
+  fn uri(item: &&'static str) -> String {
+    return item.to_string();
+  }
+
+  #[test]
+  fn it_keeps_stats_for_every_track() {
+    let log = log(vec![
+      play("spotify:track:1", "Einstürzende Neubauten", 1000, false),
+      play("spotify:track:1", "Einstürzende Neubauten", 2000, true),
+      play("spotify:track:2", "Einstürzende Neubauten", 1500, false),
+    ]);
+
+    let stats = log.stats.borrow();
+    assert_eq!(stats.get("spotify:track:1"), Some(&TrackStats { plays: 1, last_played: Some(2000) }));
+    assert_eq!(stats.get("spotify:track:3"), None);
+  }
+
+  #[test]
+  fn it_sorts_and_filters_lists_by_the_stats() {
+    let log = log(vec![
+      play("spotify:track:2", "Einstürzende Neubauten", 1000, false),
+      play("spotify:track:3", "Einstürzende Neubauten", 2000, false),
+      play("spotify:track:3", "Einstürzende Neubauten", 3000, false),
+      play("spotify:track:4", "Einstürzende Neubauten", 4000, true),
+    ]);
+
+    let mut list = list::List::new(vec!["spotify:track:1", "spotify:track:2", "spotify:track:3", "spotify:track:4"], 10);
+
+    log.sort_list(&mut list, Some(SortKey::Plays), uri);
+    assert_eq!(list.get_matching_items(), vec!["spotify:track:3", "spotify:track:2", "spotify:track:1", "spotify:track:4"]);
+
+    log.sort_list(&mut list, None, uri);
+    assert!(log.hide_played(&mut list, uri));
+    assert_eq!(list.get_matching_items(), vec!["spotify:track:1", "spotify:track:4"]);
+  }
+
+  #[test]
+  fn it_leaves_the_list_as_it_is_when_every_track_has_been_played() {
+    let log = log(vec![play("spotify:track:1", "Einstürzende Neubauten", 1000, false)]);
+    let mut list = list::List::new(vec!["spotify:track:1"], 10);
+
+    assert!(!log.hide_played(&mut list, uri));
+    assert_eq!(list.get_matching_items(), vec!["spotify:track:1"]);
+  }
+
+  #[test]
+  fn it_shows_the_configured_columns() {
+    let stats = TrackStats { plays: 12, last_played: Some(1000) };
+    let both = configuration::ListSettings { play_count: true, last_played: true };
+
+    assert_eq!(columns(stats, both, 1000 + 3 * DAY), "  12    3d ago  ");
+    assert_eq!(columns(TrackStats::default(), both, 1000), "   0     never  ");
+    assert_eq!(columns(stats, configuration::ListSettings { play_count: false, last_played: true }, 1030), "just now  ");
+  }
+}
@@ -0,0 +1,866 @@
+use configuration;
+use control;
+use curl;
+use curl::easy::{Easy, List};
+use md5;
+use plays;
+use rustc_serialize::json::{Json};
+use rustify;
+use std;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+/// The most listens submitted in one request, which is what Last.fm allows.
+const BATCH_SIZE: usize = 50;
+
+/// How often listens that couldn't be submitted are tried again.
+const RETRY_INTERVAL: u64 = 5 * 60;
+
+/// A track that has been (or is being) listened to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Listen {
+  pub artist: String,
+  pub title: String,
+  pub album: String,
+
+  /// In seconds.
+  pub duration: u64,
+
+  /// When the track started playing, in seconds since the Unix epoch.
+  pub timestamp: u64,
+}
+
+impl Listen {
+  fn new(track: &control::TrackInfo, timestamp: u64) -> Listen {
+    return Listen {
+      // The services expect the main artist rather than all of them
+      artist: track.artists.first().cloned().unwrap_or(String::new()),
+      title: track.title.clone(),
+      album: track.album.clone(),
+      duration: track.duration.as_secs(),
+      timestamp: timestamp,
+    };
+  }
+
+  fn to_json(&self) -> Json {
+    let mut object: BTreeMap<String, Json> = BTreeMap::new();
+    object.insert("artist".to_string(), Json::String(self.artist.clone()));
+    object.insert("title".to_string(), Json::String(self.title.clone()));
+    object.insert("album".to_string(), Json::String(self.album.clone()));
+    object.insert("duration".to_string(), Json::U64(self.duration));
+    object.insert("timestamp".to_string(), Json::U64(self.timestamp));
+
+    return Json::Object(object);
+  }
+
+  fn from_json(json: &Json) -> Option<Listen> {
+    let string = |name: &str| json.find(name).and_then(|value| value.as_string()).map(|value| value.to_string());
+    let number = |name: &str| json.find(name).and_then(|value| value.as_u64());
+
+    return match (string("artist"), string("title"), string("album"), number("duration"), number("timestamp")) {
+      (Some(artist), Some(title), Some(album), Some(duration), Some(timestamp)) => {
+        Some(Listen { artist: artist, title: title, album: album, duration: duration, timestamp: timestamp })
+      },
+      _ => None,
+    }
+  }
+}
+
+enum Event {
+  /// Replaces the services the listens are submitted to, after the
+  /// configuration has changed.
+  Configure(Vec<(Box<Service + Send>, PathBuf)>),
+
+  NowPlaying(Listen),
+  Listened(Listen),
+}
+
+#[derive(Debug, PartialEq)]
+enum SubmitError {
+  /// The service couldn't be reached or can't take listens right now, so
+  /// they're kept for later.
+  Retry(String),
+
+  /// The service won't ever take the listens, so there's no point in keeping
+  /// them.
+  Rejected(String),
+
+  /// The service can't be used with the settings (e.g. a wrong password), so
+  /// it's left alone until the configuration changes. The message names the
+  /// service.
+  Fatal(String),
+}
+
+/// A service listens are submitted to.
+trait Service {
+  /// The name used for the file of listens waiting to be submitted.
+  fn name(&self) -> &'static str;
+
+  fn now_playing(&mut self, listen: &Listen) -> Result<(), SubmitError>;
+
+  /// Submits at most `BATCH_SIZE` listens.
+  fn submit(&mut self, listens: &[Listen]) -> Result<(), SubmitError>;
+}
+
+/// Keeps track of how long the playing track has been listened to, and has
+/// it submitted once that's half of the track or 4 minutes. The requests are
+/// made on a thread of their own, so that a slow network doesn't hold up the
+/// interface.
+pub struct Scrobbler {
+  /// What the thread was last configured with, so that it can be given the
+  /// new services when the configuration changes.
+  settings: configuration::ScrobbleSettings,
+
+  sender: Option<Sender<Event>>,
+
+  /// The reasons services were stopped, sent back by the thread.
+  errors: Option<Receiver<String>>,
+
+  progress: Option<Progress>,
+}
+
+impl Scrobbler {
+  pub fn new() -> Scrobbler {
+    return Scrobbler {
+      settings: configuration::ScrobbleSettings::default(),
+      sender: None,
+      errors: None,
+      progress: None,
+    };
+  }
+
+  /// Follows the playback. The `generation` changes whenever a track is
+  /// started, so that a track played again is submitted again.
+  pub fn update(&mut self, config: &configuration::Config, track: Option<&rustify::Track>, generation: u64, playing: bool) {
+    if config.scrobbling != self.settings {
+      self.settings = config.scrobbling.clone();
+
+      // The thread is kept running and given the new services, so that there
+      // never are two threads submitting (and rewriting) the same queue
+      let services = services(config);
+      if self.sender.is_none() && !services.is_empty() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let (error_sender, errors) = std::sync::mpsc::channel();
+        std::thread::spawn(move || run(receiver, error_sender));
+        self.sender = Some(sender);
+        self.errors = Some(errors);
+      }
+
+      self.send(Event::Configure(services));
+    }
+
+    let enabled = self.settings.lastfm.is_some() || self.settings.listenbrainz.is_some();
+    if self.sender.is_none() || !enabled {
+      self.progress = None;
+      return;
+    }
+
+    let now = Instant::now();
+
+    if track.map(|_| generation) != self.progress.as_ref().map(|progress| progress.generation) {
+      self.progress = match track {
+        Some(track) => {
+          let listen = Listen::new(&control::TrackInfo::new(track), plays::unix_time());
+          self.send(Event::NowPlaying(listen.clone()));
+
+          Some(Progress::new(generation, listen, now))
+        },
+        None => None,
+      };
+    }
+
+    let listened = match self.progress {
+      Some(ref mut progress) => progress.advance(playing, now),
+      None => false,
+    };
+
+    if listened {
+      if let Some(listen) = self.progress.as_ref().map(|progress| progress.listen.clone()) {
+        self.send(Event::Listened(listen));
+      }
+    }
+  }
+
+  /// Why a service was stopped, if one was since the last call. Each reason
+  /// is only returned once, so that it's shown once.
+  pub fn error(&self) -> Option<String> {
+    return self.errors.as_ref().and_then(|errors| errors.try_recv().ok());
+  }
+
+  fn send(&mut self, event: Event) {
+    let sent = self.sender.as_ref().map_or(false, |sender| sender.send(event).is_ok());
+    if !sent {
+      self.sender = None;
+    }
+  }
+}
+
+/// How much of a track has been listened to.
+struct Progress {
+  /// The play generation of the track (see `Scrobbler::update`).
+  generation: u64,
+
+  listen: Listen,
+
+  /// The time spent playing (but not paused).
+  listened: Duration,
+
+  ticked_at: Instant,
+  submitted: bool,
+}
+
+impl Progress {
+  fn new(generation: u64, listen: Listen, now: Instant) -> Progress {
+    return Progress {
+      generation: generation,
+      listen: listen,
+      listened: Duration::from_secs(0),
+      ticked_at: now,
+      submitted: false,
+    };
+  }
+
+  /// Adds the time since the last tick if the track is `playing`, returning
+  /// whether it has now been listened to. Like the services ask, tracks
+  /// shorter than 30 seconds are never submitted.
+  fn advance(&mut self, playing: bool, now: Instant) -> bool {
+    if playing {
+      self.listened += now.duration_since(self.ticked_at);
+    }
+
+    self.ticked_at = now;
+
+    let required = Duration::from_secs(std::cmp::min(self.listen.duration / 2, 4 * 60));
+    if !self.submitted && self.listen.duration >= 30 && self.listened >= required {
+      self.submitted = true;
+      return true;
+    }
+
+    return false;
+  }
+}
+
+/// Returns the configured services, with the files of the listens waiting to
+/// be submitted to them.
+fn services(config: &configuration::Config) -> Vec<(Box<Service + Send>, PathBuf)> {
+  let mut services: Vec<(Box<Service + Send>, PathBuf)> = Vec::new();
+
+  if let Some(ref settings) = config.scrobbling.lastfm {
+    let service = LastFm::new(settings.clone());
+    let path = config.scrobble_queue_path(service.name());
+    services.push((Box::new(service), path));
+  }
+
+  if let Some(ref settings) = config.scrobbling.listenbrainz {
+    let service = ListenBrainz::new(settings.clone());
+    let path = config.scrobble_queue_path(service.name());
+    services.push((Box::new(service), path));
+  }
+
+  return services;
+}
+
+/// Submits the listens to the services it's been configured with, until the
+/// `Scrobbler` is dropped. The reason a service is stopped is sent to the
+/// `errors`.
+fn run(receiver: Receiver<Event>, errors: Sender<String>) {
+  let mut services: Vec<(Box<Service + Send>, PathBuf)> = Vec::new();
+
+  loop {
+    // Without a listen the waiting ones are submitted
+    let event = match receiver.recv_timeout(Duration::from_secs(RETRY_INTERVAL)) {
+      Ok(Event::Configure(new_services)) => {
+        services = new_services;
+        None
+      },
+      Ok(event) => Some(event),
+      Err(RecvTimeoutError::Timeout) => None,
+      Err(RecvTimeoutError::Disconnected) => return,
+    };
+
+    let mut working = Vec::new();
+
+    for (mut service, path) in services.drain(..) {
+      let result = match event {
+        Some(Event::NowPlaying(ref listen)) => service.now_playing(listen),
+        Some(Event::Listened(ref listen)) => {
+          let _ = append_to_queue(&path, listen);
+          flush(&mut *service, &path)
+        },
+        _ => flush(&mut *service, &path),
+      };
+
+      match result {
+        Err(SubmitError::Fatal(message)) => {
+          let _ = errors.send(format!("Stopped scrobbling to {}", message));
+        },
+        _ => working.push((service, path)),
+      }
+    }
+
+    services = working;
+  }
+}
+
+/// Submits the listens waiting in the queue, keeping the ones that couldn't
+/// be submitted for the next time. Only a `SubmitError::Fatal` is returned,
+/// since the others are dealt with here.
+fn flush(service: &mut Service, path: &Path) -> Result<(), SubmitError> {
+  let mut pending = read_queue(path);
+  if pending.is_empty() {
+    return Ok(());
+  }
+
+  // The listens of a rejected batch are submitted one at a time, so that
+  // only the ones that are actually rejected get dropped
+  let mut one_at_a_time = 0;
+
+  while !pending.is_empty() {
+    let count = if one_at_a_time > 0 { 1 } else { std::cmp::min(pending.len(), BATCH_SIZE) };
+
+    match service.submit(&pending[..count]) {
+      Ok(()) => {
+        pending.drain(..count);
+      },
+      Err(SubmitError::Rejected(_)) if count > 1 => {
+        one_at_a_time = count;
+        continue;
+      },
+      Err(SubmitError::Rejected(_)) => {
+        pending.drain(..count);
+      },
+      Err(SubmitError::Retry(_)) => break,
+      Err(SubmitError::Fatal(message)) => {
+        let _ = write_queue(path, &pending);
+        return Err(SubmitError::Fatal(message));
+      },
+    }
+
+    one_at_a_time = one_at_a_time.saturating_sub(count);
+  }
+
+  let _ = write_queue(path, &pending);
+  return Ok(());
+}
+
+/// Reads the listens waiting to be submitted, one JSON object per line.
+fn read_queue(path: &Path) -> Vec<Listen> {
+  let file = match std::fs::File::open(path) {
+    Ok(file) => file,
+    Err(_) => return Vec::new(),
+  };
+
+  return BufReader::new(file).lines()
+    .filter_map(|line| line.ok())
+    .filter_map(|line| Json::from_str(&line).ok())
+    .filter_map(|json| Listen::from_json(&json))
+    .collect();
+}
+
+fn append_to_queue(path: &Path, listen: &Listen) -> std::io::Result<()> {
+  let mut file = try!(std::fs::OpenOptions::new().create(true).append(true).open(path));
+  return writeln!(file, "{}", listen.to_json());
+}
+
+/// Replaces the queue with the `listens`, removing it when there are none.
+fn write_queue(path: &Path, listens: &[Listen]) -> std::io::Result<()> {
+  if listens.is_empty() {
+    return match std::fs::remove_file(path) {
+      Err(ref e) if e.kind() != std::io::ErrorKind::NotFound => Err(std::io::Error::new(e.kind(), e.to_string())),
+      _ => Ok(()),
+    }
+  }
+
+  let mut temporary_path = path.as_os_str().to_os_string();
+  temporary_path.push(".tmp");
+
+  {
+    let mut file = try!(std::fs::File::create(&temporary_path));
+    for listen in listens {
+      try!(writeln!(file, "{}", listen.to_json()));
+    }
+  }
+
+  return std::fs::rename(&temporary_path, path);
+}
+
+struct LastFm {
+  settings: configuration::LastFmSettings,
+
+  /// Got by signing in with the username and password, the first time it's
+  /// needed.
+  session_key: Option<String>,
+}
+
+impl LastFm {
+  fn new(settings: configuration::LastFmSettings) -> LastFm {
+    return LastFm { settings: settings, session_key: None };
+  }
+
+  fn session_key(&mut self) -> Result<String, SubmitError> {
+    if let Some(ref session_key) = self.session_key {
+      return Ok(session_key.clone());
+    }
+
+    let password = try!(self.settings.password.read().map_err(|e| SubmitError::Retry(e.to_string())));
+    let parameters = vec![
+      ("username".to_string(), self.settings.username.clone()),
+      ("password".to_string(), password),
+    ];
+
+    let response = try!(self.call("auth.getMobileSession", parameters));
+    let session_key = match response.find_path(&["session", "key"]).and_then(|key| key.as_string()) {
+      Some(session_key) => session_key.to_string(),
+      None => return Err(SubmitError::Retry("Last.fm didn't return a session key".to_string())),
+    };
+
+    self.session_key = Some(session_key.clone());
+    return Ok(session_key);
+  }
+
+  /// Calls an API method with the `parameters`, signed with the secret.
+  fn call(&mut self, method: &str, parameters: Vec<(String, String)>) -> Result<Json, SubmitError> {
+    let mut parameters = parameters;
+    parameters.push(("method".to_string(), method.to_string()));
+    parameters.push(("api_key".to_string(), self.settings.api_key.clone()));
+
+    let signature = lastfm_signature(&parameters, &self.settings.api_secret);
+    parameters.push(("api_sig".to_string(), signature));
+    parameters.push(("format".to_string(), "json".to_string()));
+
+    let body = form_encode(&parameters);
+    let headers = ["Content-Type: application/x-www-form-urlencoded".to_string()];
+    let (_, response) = try!(post(&self.settings.url, &headers, body.as_bytes()).map_err(SubmitError::Retry));
+
+    let json = match Json::from_str(&response) {
+      Ok(json) => json,
+      Err(_) => return Err(SubmitError::Retry(format!("Unexpected response from Last.fm: {}", response))),
+    };
+
+    return match json.find("error").and_then(|error| error.as_u64()) {
+      None => Ok(json),
+      Some(code) => {
+        let message = json.find("message").and_then(|message| message.as_string()).unwrap_or("").to_string();
+
+        // An invalid session key has to be replaced by a new one
+        if code == 9 {
+          self.session_key = None;
+        }
+
+        // Invalid parameters won't get any better by trying again, and
+        // neither will a wrong password (4) or an invalid or suspended API
+        // key (10 and 26). Anything else might (e.g. the service being
+        // offline)
+        match code {
+          6 => Err(SubmitError::Rejected(message)),
+          4 | 10 | 26 => Err(SubmitError::Fatal(format!("Last.fm: {}", message))),
+          _ => Err(SubmitError::Retry(message)),
+        }
+      },
+    }
+  }
+}
+
+impl Service for LastFm {
+  fn name(&self) -> &'static str {
+    return "lastfm";
+  }
+
+  fn now_playing(&mut self, listen: &Listen) -> Result<(), SubmitError> {
+    let session_key = try!(self.session_key());
+    let parameters = vec![
+      ("sk".to_string(), session_key),
+      ("artist".to_string(), listen.artist.clone()),
+      ("track".to_string(), listen.title.clone()),
+      ("album".to_string(), listen.album.clone()),
+      ("duration".to_string(), listen.duration.to_string()),
+    ];
+
+    return self.call("track.updateNowPlaying", parameters).map(|_| ());
+  }
+
+  fn submit(&mut self, listens: &[Listen]) -> Result<(), SubmitError> {
+    let mut parameters = vec![("sk".to_string(), try!(self.session_key()))];
+
+    for (index, listen) in listens.iter().enumerate() {
+      parameters.push((format!("artist[{}]", index), listen.artist.clone()));
+      parameters.push((format!("track[{}]", index), listen.title.clone()));
+      parameters.push((format!("album[{}]", index), listen.album.clone()));
+      parameters.push((format!("duration[{}]", index), listen.duration.to_string()));
+      parameters.push((format!("timestamp[{}]", index), listen.timestamp.to_string()));
+    }
+
+    return self.call("track.scrobble", parameters).map(|_| ());
+  }
+}
+
+struct ListenBrainz {
+  settings: configuration::ListenBrainzSettings,
+}
+
+impl ListenBrainz {
+  fn new(settings: configuration::ListenBrainzSettings) -> ListenBrainz {
+    return ListenBrainz { settings: settings };
+  }
+
+  /// Submits the listens, as `playing_now`, `single` or `import`.
+  fn submit_listens(&self, listen_type: &str, listens: &[Listen], timestamps: bool) -> Result<(), SubmitError> {
+    let payload: Vec<Json> = listens.iter().map(|listen| {
+      let mut metadata: BTreeMap<String, Json> = BTreeMap::new();
+      metadata.insert("artist_name".to_string(), Json::String(listen.artist.clone()));
+      metadata.insert("track_name".to_string(), Json::String(listen.title.clone()));
+      metadata.insert("release_name".to_string(), Json::String(listen.album.clone()));
+
+      let mut object: BTreeMap<String, Json> = BTreeMap::new();
+      object.insert("track_metadata".to_string(), Json::Object(metadata));
+      if timestamps {
+        object.insert("listened_at".to_string(), Json::U64(listen.timestamp));
+      }
+
+      Json::Object(object)
+    }).collect();
+
+    let mut request: BTreeMap<String, Json> = BTreeMap::new();
+    request.insert("listen_type".to_string(), Json::String(listen_type.to_string()));
+    request.insert("payload".to_string(), Json::Array(payload));
+
+    let url = format!("{}/1/submit-listens", self.settings.url.trim_right_matches('/'));
+    let headers = [
+      format!("Authorization: Token {}", self.settings.token),
+      "Content-Type: application/json".to_string(),
+    ];
+
+    let (code, response) = try!(post(&url, &headers, Json::Object(request).to_string().as_bytes()).map_err(SubmitError::Retry));
+
+    return match code {
+      200 => Ok(()),
+      400 => Err(SubmitError::Rejected(response)),
+      401 => {
+        // The reason is given like `{"code": 401, "error": "..."}`
+        let error = Json::from_str(&response).ok()
+          .and_then(|json| json.find("error").and_then(|error| error.as_string()).map(|error| error.to_string()));
+
+        Err(SubmitError::Fatal(format!("ListenBrainz: {}", error.unwrap_or(response))))
+      },
+      _ => Err(SubmitError::Retry(format!("ListenBrainz answered {}: {}", code, response))),
+    }
+  }
+}
+
+impl Service for ListenBrainz {
+  fn name(&self) -> &'static str {
+    return "listenbrainz";
+  }
+
+  fn now_playing(&mut self, listen: &Listen) -> Result<(), SubmitError> {
+    return self.submit_listens("playing_now", &[listen.clone()], false);
+  }
+
+  fn submit(&mut self, listens: &[Listen]) -> Result<(), SubmitError> {
+    let listen_type = if listens.len() == 1 { "single" } else { "import" };
+    return self.submit_listens(listen_type, listens, true);
+  }
+}
+
+/// Makes a POST request, returning the status code and the body of the
+/// response.
+fn post(url: &str, headers: &[String], body: &[u8]) -> Result<(u32, String), String> {
+  let mut easy = Easy::new();
+  let mut response: Vec<u8> = Vec::new();
+  let mut header_list = List::new();
+
+  for header in headers {
+    try!(header_list.append(header).map_err(curl_error));
+  }
+
+  try!(easy.url(url).map_err(curl_error));
+  try!(easy.post(true).map_err(curl_error));
+  try!(easy.post_fields_copy(body).map_err(curl_error));
+  try!(easy.http_headers(header_list).map_err(curl_error));
+  try!(easy.timeout(Duration::from_secs(30)).map_err(curl_error));
+
+  {
+    let mut transfer = easy.transfer();
+    try!(transfer.write_function(|data| {
+      response.extend_from_slice(data);
+      return Ok(data.len());
+    }).map_err(curl_error));
+    try!(transfer.perform().map_err(curl_error));
+  }
+
+  let code = try!(easy.response_code().map_err(curl_error));
+  return Ok((code, String::from_utf8_lossy(&response).into_owned()));
+}
+
+fn curl_error(error: curl::Error) -> String {
+  return error.to_string();
+}
+
+/// The `api_sig` of a Last.fm call: the MD5 of the parameters ordered by
+/// name and concatenated, followed by the secret.
+fn lastfm_signature(parameters: &[(String, String)], secret: &str) -> String {
+  let mut sorted: Vec<&(String, String)> = parameters.iter().collect();
+  sorted.sort();
+
+  let mut data = String::new();
+  for &&(ref name, ref value) in &sorted {
+    data.push_str(name);
+    data.push_str(value);
+  }
+  data.push_str(secret);
+
+  return format!("{:x}", md5::compute(data.as_bytes()));
+}
+
+/// Encodes the parameters as `application/x-www-form-urlencoded`.
+fn form_encode(parameters: &[(String, String)]) -> String {
+  let encode = |value: &str| {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+      match byte {
+        b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+        b' ' => encoded.push('+'),
+        _ => encoded.push_str(&format!("%{:02X}", byte)),
+      }
+    }
+    encoded
+  };
+
+  return parameters.iter()
+    .map(|&(ref name, ref value)| format!("{}={}", encode(name), encode(value)))
+    .collect::<Vec<String>>()
+    .join("&");
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use configuration;
+  use std;
+  use std::io::{BufRead, BufReader, Read, Write};
+  use std::net::{TcpListener};
+  use std::time::{Duration, Instant};
+  use test_fixtures::{temporary_path};
+
+  fn listen(title: &str, timestamp: u64) -> Listen {
+    return Listen {
+      artist: "Einstürzende Neubauten".to_string(),
+      title: title.to_string(),
+      album: "Tabula Rasa".to_string(),
+      duration: 254,
+      timestamp: timestamp,
+    };
+  }
+
+  /// A stand-in for the services, answering each request with the next of
+  /// the `responses` and sending back the requests it got (headers and body).
+  fn serve(responses: Vec<(u32, &'static str)>) -> (String, std::sync::mpsc::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    std::thread::spawn(move || {
+      for (code, body) in responses {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request = String::new();
+        let mut content_length = 0;
+
+        loop {
+          let mut line = String::new();
+          reader.read_line(&mut line).unwrap();
+          if line.to_lowercase().starts_with("content-length:") {
+            content_length = line[15..].trim().parse().unwrap();
+          }
+          if line == "\r\n" {
+            break;
+          }
+          request.push_str(&line);
+        }
+
+        let mut content = vec![0; content_length];
+        reader.read_exact(&mut content).unwrap();
+        request.push_str(&String::from_utf8(content).unwrap());
+
+        write!(stream, "HTTP/1.1 {} Whatever\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", code, body.len(), body).unwrap();
+        sender.send(request).unwrap();
+      }
+    });
+
+    return (url, receiver);
+  }
+
+  #[test]
+  fn it_submits_after_half_the_track_or_four_minutes() {
+    let start = Instant::now();
+    let mut progress = Progress::new(1, listen("Zebulon", 0), start);
+
+    assert!(!progress.advance(true, start + Duration::from_secs(100)));
+
+    // Paused time doesn't count
+    assert!(!progress.advance(false, start + Duration::from_secs(200)));
+    assert!(progress.advance(true, start + Duration::from_secs(230)));
+    assert!(!progress.advance(true, start + Duration::from_secs(240)));
+
+    let mut long = listen("Headcleaner", 0);
+    long.duration = 600;
+    let mut progress = Progress::new(2, long, start);
+    assert!(progress.advance(true, start + Duration::from_secs(240)));
+  }
+
+  #[test]
+  fn it_signs_lastfm_calls() {
+    let parameters = vec![
+      ("method".to_string(), "auth.getMobileSession".to_string()),
+      ("api_key".to_string(), "key".to_string()),
+    ];
+
+    // md5("api_keykeymethodauth.getMobileSessionsecret")
+    assert_eq!(lastfm_signature(&parameters, "secret"), "018322def6bdaf0b7eba8f03ac376100");
+  }
+
+  #[test]
+  fn it_form_encodes_parameters() {
+    let parameters = vec![
+      ("artist[0]".to_string(), "Einstürzende Neubauten".to_string()),
+      ("track[0]".to_string(), "Blume & Co".to_string()),
+    ];
+
+    assert_eq!(form_encode(&parameters), "artist%5B0%5D=Einst%C3%BCrzende+Neubauten&track%5B0%5D=Blume+%26+Co");
+  }
+
+  #[test]
+  fn it_submits_to_listenbrainz() {
+    let (url, requests) = serve(vec![(200, r#"{"status": "ok"}"#)]);
+    let mut service = ListenBrainz::new(configuration::ListenBrainzSettings {
+      token: "abc".to_string(),
+      url: url,
+    });
+
+    assert_eq!(service.submit(&[listen("Zebulon", 1000)]), Ok(()));
+
+    let request = requests.recv().unwrap();
+    assert!(request.starts_with("POST /1/submit-listens "));
+    assert!(request.contains("Authorization: Token abc\r\n"));
+    assert!(request.ends_with(r#"{"listen_type":"single","payload":[{"listened_at":1000,"track_metadata":{"artist_name":"Einstürzende Neubauten","release_name":"Tabula Rasa","track_name":"Zebulon"}}]}"#));
+  }
+
+  #[test]
+  fn it_signs_in_to_lastfm_before_scrobbling() {
+    let (url, requests) = serve(vec![
+      (200, r#"{"session": {"name": "blixa", "key": "session-key", "subscriber": 0}}"#),
+      (200, r#"{"scrobbles": {}}"#),
+    ]);
+
+    let mut service = LastFm::new(configuration::LastFmSettings {
+      api_key: "key".to_string(),
+      api_secret: "secret".to_string(),
+      username: "blixa".to_string(),
+      password: configuration::PasswordSource::Plain("hunter2".to_string()),
+      url: url,
+    });
+
+    assert_eq!(service.submit(&[listen("Zebulon", 1000)]), Ok(()));
+
+    assert!(requests.recv().unwrap().contains("method=auth.getMobileSession"));
+    let scrobble = requests.recv().unwrap();
+    assert!(scrobble.contains("method=track.scrobble"));
+    assert!(scrobble.contains("sk=session-key"));
+    assert!(scrobble.contains("timestamp%5B0%5D=1000"));
+  }
+
+  #[test]
+  fn it_keeps_the_listens_that_could_not_be_submitted() {
+    let path = temporary_path("lastfm_queue");
+    let _ = std::fs::remove_file(&path);
+
+    append_to_queue(&path, &listen("Zebulon", 1000)).unwrap();
+    append_to_queue(&path, &listen("Headcleaner", 2000)).unwrap();
+
+    // Nothing is listening on the port of a listener that has been dropped
+    let url = format!("http://{}", TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap());
+    let mut service = ListenBrainz::new(configuration::ListenBrainzSettings { token: "abc".to_string(), url: url });
+    assert_eq!(flush(&mut service, &path), Ok(()));
+
+    assert_eq!(read_queue(&path), vec![listen("Zebulon", 1000), listen("Headcleaner", 2000)]);
+
+    let (url, _requests) = serve(vec![(200, r#"{"status": "ok"}"#)]);
+    let mut service = ListenBrainz::new(configuration::ListenBrainzSettings { token: "abc".to_string(), url: url });
+    assert_eq!(flush(&mut service, &path), Ok(()));
+
+    assert!(!path.exists());
+  }
+
+  #[test]
+  fn it_stops_submitting_to_lastfm_with_a_wrong_password() {
+    let path = temporary_path("refused_queue");
+    let _ = std::fs::remove_file(&path);
+    append_to_queue(&path, &listen("Zebulon", 1000)).unwrap();
+
+    let (url, _requests) = serve(vec![(403, r#"{"error": 4, "message": "Authentication Failed"}"#)]);
+    let service = LastFm::new(configuration::LastFmSettings {
+      api_key: "key".to_string(),
+      api_secret: "secret".to_string(),
+      username: "blixa".to_string(),
+      password: configuration::PasswordSource::Plain("hunter3".to_string()),
+      url: url,
+    });
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let (error_sender, errors) = std::sync::mpsc::channel();
+    std::thread::spawn(move || run(receiver, error_sender));
+
+    let services: Vec<(Box<Service + Send>, PathBuf)> = vec![(Box::new(service), path.clone())];
+    sender.send(Event::Configure(services)).unwrap();
+    assert_eq!(errors.recv_timeout(Duration::from_secs(5)).unwrap(), "Stopped scrobbling to Last.fm: Authentication Failed");
+
+    // The service isn't asked again, and the listens are kept for when the
+    // configuration has been fixed
+    sender.send(Event::Listened(listen("Headcleaner", 2000))).unwrap();
+    assert!(errors.recv_timeout(Duration::from_millis(200)).is_err());
+    assert_eq!(read_queue(&path), vec![listen("Zebulon", 1000)]);
+  }
+
+  /// Takes every listen, unless there's one without a title in the batch.
+  struct Picky {
+    submitted: Vec<Listen>,
+  }
+
+  impl Service for Picky {
+    fn name(&self) -> &'static str {
+      return "picky";
+    }
+
+    fn now_playing(&mut self, _: &Listen) -> Result<(), SubmitError> {
+      return Ok(());
+    }
+
+    fn submit(&mut self, listens: &[Listen]) -> Result<(), SubmitError> {
+      if listens.iter().any(|listen| listen.title.is_empty()) {
+        return Err(SubmitError::Rejected("Invalid parameters".to_string()));
+      }
+
+      self.submitted.extend_from_slice(listens);
+      return Ok(());
+    }
+  }
+
+  #[test]
+  fn it_only_drops_the_listens_that_are_rejected() {
+    let path = temporary_path("picky_queue");
+    let _ = std::fs::remove_file(&path);
+
+    append_to_queue(&path, &listen("Zebulon", 1000)).unwrap();
+    append_to_queue(&path, &listen("", 2000)).unwrap();
+    append_to_queue(&path, &listen("Headcleaner", 3000)).unwrap();
+
+    let mut service = Picky { submitted: Vec::new() };
+    assert_eq!(flush(&mut service, &path), Ok(()));
+
+    assert_eq!(service.submitted, vec![listen("Zebulon", 1000), listen("Headcleaner", 3000)]);
+    assert!(!path.exists());
+  }
+}
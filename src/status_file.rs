@@ -0,0 +1,283 @@
+use control;
+use libc;
+use rustify;
+use std;
+use std::fs::{File, OpenOptions};
+use std::io::{Write};
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The fields describing the track, like `{title}`.
+const TRACK_FIELDS: [&'static str; 6] = ["track", "title", "artist", "album", "uri", "duration"];
+
+/// The fields describing the playback, which only the status has.
+const PLAYBACK_FIELDS: [&'static str; 3] = ["elapsed", "state", "paused"];
+
+/// Keeps a file (or named pipe) up to date with what's playing, for status
+/// bars to show. The file is only written when its contents change.
+pub struct StatusFile {
+  /// The named pipe, which is kept open so that the reader doesn't see the
+  /// end of the file after every line.
+  pipe: Option<(PathBuf, File)>,
+
+  /// The path and contents of the last successful write.
+  written: Option<(PathBuf, String)>,
+}
+
+impl StatusFile {
+  pub fn new() -> StatusFile {
+    return StatusFile { pipe: None, written: None };
+  }
+
+  pub fn update(&mut self,
+                path: &Path,
+                format: &str,
+                track: Option<&rustify::Track>,
+                playing: bool,
+                position: Duration) {
+    let track = track.map(control::TrackInfo::new);
+    let status = format_status(format, track.as_ref(), playing, position);
+
+    if let Some((ref written_path, ref written)) = self.written {
+      if written_path == path && written == &status {
+        return;
+      }
+    }
+
+    let is_pipe = std::fs::metadata(path).map(|metadata| metadata.file_type().is_fifo()).unwrap_or(false);
+    let result = if is_pipe { self.write_pipe(path, &status) } else { write_file(path, &status) };
+
+    self.written = match result {
+      Ok(()) => Some((path.to_path_buf(), status)),
+      Err(_) => None,
+    };
+  }
+
+  /// Writes a line to the named pipe, unless nobody is reading it (in which
+  /// case it's tried again on the next update).
+  fn write_pipe(&mut self, path: &Path, status: &str) -> std::io::Result<()> {
+    let reopen = match self.pipe {
+      Some((ref pipe_path, _)) => pipe_path != path,
+      None => true,
+    };
+
+    if reopen {
+      // Without a reader opening the pipe fails right away instead of
+      // blocking the event loop
+      let file = try!(OpenOptions::new().write(true).custom_flags(libc::O_NONBLOCK).open(path));
+      self.pipe = Some((path.to_path_buf(), file));
+    }
+
+    let result = match self.pipe {
+      Some((_, ref mut file)) => writeln!(file, "{}", status),
+      None => Ok(()),
+    };
+
+    // The reader has gone away, so wait for another one
+    if result.is_err() {
+      self.pipe = None;
+    }
+
+    return result;
+  }
+}
+
+impl Drop for StatusFile {
+  /// Removes the file so that status bars don't keep showing the last track.
+  fn drop(&mut self) {
+    if self.pipe.is_none() {
+      if let Some((ref path, _)) = self.written {
+        let _ = std::fs::remove_file(path);
+      }
+    }
+  }
+}
+
+/// Replaces the file in one go so that readers never see it half written.
+fn write_file(path: &Path, status: &str) -> std::io::Result<()> {
+  let mut temporary_path = path.as_os_str().to_os_string();
+  temporary_path.push(".tmp");
+
+  {
+    let mut file = try!(File::create(&temporary_path));
+    try!(writeln!(file, "{}", status));
+  }
+
+  return std::fs::rename(&temporary_path, path);
+}
+
+/// Returns an error naming the first unknown field in the status format, if
+/// any.
+pub fn check_format(format: &str) -> Result<(), String> {
+  let fields: Vec<&str> = TRACK_FIELDS.iter().chain(PLAYBACK_FIELDS.iter()).cloned().collect();
+  return check_fields(format, &fields);
+}
+
+/// Like `check_format`, but only the fields describing the track are known.
+pub fn check_track_format(format: &str) -> Result<(), String> {
+  return check_fields(format, &TRACK_FIELDS);
+}
+
+fn check_fields(format: &str, fields: &[&str]) -> Result<(), String> {
+  let mut rest = format;
+
+  while let Some(start) = rest.find('{') {
+    let end = match rest[start..].find('}') {
+      Some(end) => start + end,
+      None => return Err("missing `}`".to_string()),
+    };
+
+    let field = &rest[start + 1..end];
+    if !fields.contains(&field) {
+      return Err(format!("unknown field `{{{}}}`, expected one of {{{}}}", field, fields.join("}, {")));
+    }
+
+    rest = &rest[end + 1..];
+  }
+
+  return Ok(());
+}
+
+/// Fills in the fields of the format. Nothing is written when nothing is
+/// playing, so that status bars can hide it.
+pub fn format_status(format: &str,
+                     track: Option<&control::TrackInfo>,
+                     playing: bool,
+                     position: Duration) -> String {
+  let track = match track {
+    Some(track) => track,
+    None => return String::new(),
+  };
+
+  return fill_in(format, |field| {
+    return match field {
+      "elapsed" => Some(minutes(position)),
+      "state" => Some(if playing { "playing" } else { "paused" }.to_string()),
+      "paused" => Some(if playing { "" } else { "[Paused]" }.to_string()),
+      _ => track_field(track, field),
+    }
+  });
+}
+
+/// Fills in the fields of a format checked with `check_track_format`, for
+/// when there's no playback to describe.
+pub fn format_track(format: &str, track: &control::TrackInfo) -> String {
+  return fill_in(format, |field| track_field(track, field));
+}
+
+fn track_field(track: &control::TrackInfo, field: &str) -> Option<String> {
+  return match field {
+    "track" => Some(track.label.clone()),
+    "title" => Some(track.title.clone()),
+    "artist" => Some(track.artists.join(", ")),
+    "album" => Some(track.album.clone()),
+    "uri" => Some(track.uri.clone()),
+    "duration" => Some(minutes(track.duration)),
+    _ => None,
+  }
+}
+
+/// Replaces every `{field}` with its value. Unknown fields are left as they
+/// are.
+fn fill_in<F: Fn(&str) -> Option<String>>(format: &str, value: F) -> String {
+  let mut filled = String::new();
+  let mut rest = format;
+
+  while let Some(start) = rest.find('{') {
+    filled.push_str(&rest[..start]);
+
+    let end = match rest[start..].find('}') {
+      Some(end) => start + end,
+      None => break,
+    };
+
+    match value(&rest[start + 1..end]) {
+      Some(value) => filled.push_str(&value),
+      None => filled.push_str(&rest[start..end + 1]),
+    }
+
+    rest = &rest[end + 1..];
+  }
+
+  filled.push_str(rest);
+  return filled;
+}
+
+fn minutes(duration: Duration) -> String {
+  return format!("{}:{:02}", duration.as_secs() / 60, duration.as_secs() % 60);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std;
+  use std::ffi::CString;
+  use std::io::{Read};
+  use std::os::unix::fs::{OpenOptionsExt};
+  use std::time::Duration;
+  use test_fixtures::{temporary_path, track};
+
+  #[test]
+  fn it_fills_in_the_fields() {
+    let status = format_status("{artist} - {title} [{elapsed}/{duration}] {paused}",
+                               Some(&track("spotify:track:1", "Zebulon")), false, Duration::from_secs(65));
+
+    assert_eq!(status, "Einstürzende Neubauten - Zebulon [1:05/4:14] [Paused]");
+    assert_eq!(format_status("{title}", None, true, Duration::from_secs(0)), "");
+  }
+
+  #[test]
+  fn it_rejects_unknown_fields() {
+    assert!(check_format("{track} [{elapsed}/{duration}]{paused}").is_ok());
+    assert!(check_format("{artists}").is_err());
+    assert!(check_format("{title").is_err());
+  }
+
+  #[test]
+  fn it_only_knows_the_track_fields_without_playback() {
+    let track = track("spotify:track:1", "Zebulon");
+
+    assert_eq!(format_track("{title} ({duration})", &track), "Zebulon (4:14)");
+    assert!(check_track_format("{track} {uri}").is_ok());
+    assert!(check_track_format("{title} [{elapsed}]").is_err());
+    assert!(check_track_format("{paused}").is_err());
+  }
+
+  #[test]
+  fn it_replaces_the_file() {
+    let path = temporary_path("status");
+
+    write_file(&path, "Zebulon").unwrap();
+    write_file(&path, "Tabula Rasa").unwrap();
+
+    let mut contents = String::new();
+    std::fs::File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(contents, "Tabula Rasa\n");
+  }
+
+  #[test]
+  fn it_writes_lines_to_a_named_pipe_with_a_reader() {
+    let path = temporary_path("status-pipe");
+    let c_path = CString::new(path.to_str().unwrap()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+
+    let mut status_file = StatusFile::new();
+
+    // Nobody is reading yet, which mustn't block
+    assert!(status_file.write_pipe(&path, "Zebulon").is_err());
+
+    let mut reader = std::fs::OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(&path).unwrap();
+    status_file.write_pipe(&path, "Zebulon").unwrap();
+    status_file.write_pipe(&path, "Tabula Rasa").unwrap();
+    drop(status_file);
+
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents).unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(contents, "Zebulon\nTabula Rasa\n");
+  }
+}
@@ -0,0 +1,76 @@
+use control::{TrackInfo};
+use libc;
+use std;
+use std::path::{PathBuf};
+use std::time::Duration;
+
+/// A track by Einstürzende Neubauten on Tabula Rasa.
+pub fn track(uri: &str, title: &str) -> TrackInfo {
+  return TrackInfo {
+    uri: uri.to_string(),
+    label: title.to_string(),
+    title: title.to_string(),
+    artists: vec!["Einstürzende Neubauten".to_string()],
+    album: "Tabula Rasa".to_string(),
+    duration: Duration::from_secs(254),
+  };
+}
+
+/// Returns a path in the temporary directory that's unique to the `name`
+/// and this test run.
+pub fn temporary_path(name: &str) -> PathBuf {
+  return std::env::temp_dir().join(format!("neubauten-{}-{}", name, unsafe { libc::getpid() }));
+}
+
+#[cfg(any(feature = "mpris", feature = "notifications"))]
+pub use self::session_bus::{session_bus};
+
+#[cfg(any(feature = "mpris", feature = "notifications"))]
+mod session_bus {
+  use std;
+  use std::io::{BufRead, BufReader};
+  use std::process::{Child, Command, Stdio};
+  use std::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+  use std::time::Duration;
+
+  static IN_USE: AtomicBool = ATOMIC_BOOL_INIT;
+
+  /// A `dbus-daemon` of its own, which is the session bus until it's
+  /// dropped, so that the tests don't talk to the desktop.
+  pub struct SessionBus {
+    daemon: Child,
+  }
+
+  /// Starts the daemon. The address is shared by the whole process, so
+  /// the tests using it wait for each other.
+  pub fn session_bus() -> SessionBus {
+    while IN_USE.compare_and_swap(false, true, Ordering::SeqCst) {
+      std::thread::sleep(Duration::from_millis(10));
+    }
+
+    let mut daemon = match Command::new("dbus-daemon")
+      .args(&["--session", "--print-address", "--nofork"])
+      .stdout(Stdio::piped())
+      .spawn() {
+      Ok(daemon) => daemon,
+      Err(e) => {
+        IN_USE.store(false, Ordering::SeqCst);
+        panic!("Unable to start dbus-daemon: {}", e);
+      },
+    };
+
+    let mut address = String::new();
+    let _ = BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address);
+    std::env::set_var("DBUS_SESSION_BUS_ADDRESS", address.trim());
+
+    return SessionBus { daemon: daemon };
+  }
+
+  impl Drop for SessionBus {
+    fn drop(&mut self) {
+      let _ = self.daemon.kill();
+      let _ = self.daemon.wait();
+      IN_USE.store(false, Ordering::SeqCst);
+    }
+  }
+}
@@ -1,11 +1,133 @@
 use list;
+use playlist_tree;
+use plays;
 use rustify;
 
+use std::fmt;
+
+/// The number of tracks and artists shown in the `StatsView`.
+const TOP_COUNT: usize = 25;
+
 pub enum NeubautenView {
-  PlaylistView(list::List<rustify::Playlist>),
+  PlaylistView(playlist_tree::PlaylistTree<rustify::Playlist>, list::List<playlist_tree::Row<rustify::Playlist>>),
 
   #[allow(dead_code)]
   TrackView(rustify::Playlist, list::List<rustify::Track>),
 
   SearchView(rustify::Search, list::List<rustify::Track>),
+
+  AlbumView(rustify::AlbumBrowse, list::List<AlbumTrack>),
+
+  ArtistView(rustify::ArtistBrowse, list::List<ArtistItem>),
+
+  /// Lets the user pick which playlist the tracks should be added to.
+  PlaylistPickerView(Vec<rustify::Track>, list::List<rustify::Playlist>),
+
+  /// The most played tracks and artists during the period.
+  StatsView(plays::Period, list::List<StatsItem>),
+}
+
+/// A row in the `AlbumView`, which shows the disc and track number in front of
+/// the track.
+#[derive(Clone)]
+pub struct AlbumTrack {
+  pub track: rustify::Track,
+}
+
+impl fmt::Display for AlbumTrack {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    return write!(f, "{}.{:02}  {}", self.track.disc(), self.track.index(), self.track);
+  }
+}
+
+/// Returns the URI of the track, for looking it up in the log of played
+/// tracks.
+pub fn track_uri(track: &rustify::Track) -> String {
+  return track.uri();
+}
+
+pub fn album_track_uri(item: &AlbumTrack) -> String {
+  return item.track.uri();
+}
+
+/// A row in the `ArtistView`, where the different sections of an artist are
+/// shown in the same list.
+#[derive(Clone)]
+pub enum ArtistItem {
+  TopTrack(rustify::Track),
+  Album(rustify::Album),
+  Single(rustify::Album),
+  RelatedArtist(rustify::Artist),
+}
+
+impl fmt::Display for ArtistItem {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    return match self {
+      &ArtistItem::TopTrack(ref track) => write!(f, "Top track  {}", track),
+      &ArtistItem::Album(ref album) => write!(f, "Album      {} ({})", album, album.year()),
+      &ArtistItem::Single(ref album) => write!(f, "Single     {} ({})", album, album.year()),
+      &ArtistItem::RelatedArtist(ref artist) => write!(f, "Related    {}", artist),
+    }
+  }
+}
+
+/// Returns the rows of the `ArtistView` for the given `browse`: top tracks
+/// first, followed by albums, singles and related artists.
+pub fn artist_items(browse: &rustify::ArtistBrowse) -> Vec<ArtistItem> {
+  let mut items: Vec<ArtistItem> = Vec::new();
+  let mut singles: Vec<ArtistItem> = Vec::new();
+
+  for track in browse.top_tracks() {
+    items.push(ArtistItem::TopTrack(track));
+  }
+
+  for album in browse.albums() {
+    match album.album_type() {
+      rustify::AlbumType::Single => singles.push(ArtistItem::Single(album)),
+      _ => items.push(ArtistItem::Album(album)),
+    }
+  }
+
+  items.extend(singles);
+
+  for artist in browse.similar_artists() {
+    items.push(ArtistItem::RelatedArtist(artist));
+  }
+
+  return items;
+}
+
+/// A row in the `StatsView`, with the number of times the track or artist has
+/// been played.
+#[derive(Clone)]
+pub enum StatsItem {
+  /// Contains the play from the log, and the track if it could be found.
+  Track(usize, plays::Play, Option<rustify::Track>),
+  Artist(usize, String),
+}
+
+impl fmt::Display for StatsItem {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    return match self {
+      &StatsItem::Track(plays, ref play, _) => write!(f, "Track   {:>4}  {}", plays, play.label()),
+      &StatsItem::Artist(plays, ref artist) => write!(f, "Artist  {:>4}  {}", plays, artist),
+    }
+  }
+}
+
+/// Returns the rows of the `StatsView`: the most played tracks first,
+/// followed by the most played artists.
+pub fn stats_items(session: &rustify::Session, plays: &[plays::Play], period: plays::Period, now: u64) -> Vec<StatsItem> {
+  let mut items: Vec<StatsItem> = Vec::new();
+
+  for (count, play) in plays::top_tracks(plays, period, now, TOP_COUNT) {
+    let track = session.track_from_uri(&play.uri);
+    items.push(StatsItem::Track(count, play, track));
+  }
+
+  for (count, artist) in plays::top_artists(plays, period, now, TOP_COUNT) {
+    items.push(StatsItem::Artist(count, artist));
+  }
+
+  return items;
 }